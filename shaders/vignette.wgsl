@@ -0,0 +1,12 @@
+// Example post-processing shader, run with `emu8 --shader shaders/vignette.wgsl rom.ch8`.
+// See src/post_process.wgsl for the inputs available to shaders.
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv).rgb;
+    let centered = in.uv * 2.0 - 1.0;
+    let vignette = 1.0 - 0.35 * dot(centered, centered);
+    let pulse = 0.97 + 0.03 * sin(globals.time * 2.0);
+    let tint = mix(vec3<f32>(1.0, 1.0, 1.0), globals.palette[1].rgb, 0.1);
+    return vec4<f32>(color * tint * vignette * pulse, 1.0);
+}
@@ -0,0 +1,155 @@
+use std::{fmt, hint, time::{Duration, Instant}};
+use crate::{framebuffer::Framebuffer, input, instruction::Instruction, persistence::Persistence, processor::ProcessorState, config::Config, Rom, RunError};
+
+const CLASSES: [&str; 10] = ["flow", "skip", "load", "arithmetic", "random", "index", "memory", "display", "timer", "input"];
+const CALIBRATION_ROUNDS: usize = 10;
+const CALIBRATION_SAMPLES: u32 = 10_000;
+
+fn class(instruction: Instruction) -> usize {
+    match instruction {
+        Instruction::Return | Instruction::MachineCall(_) | Instruction::Jump(_) | Instruction::Call(_)
+        | Instruction::JumpOffset(..) | Instruction::Invalid => 0,
+        Instruction::SkipEqual(..) | Instruction::SkipNotEqual(..) | Instruction::SkipRegistersEqual(..)
+        | Instruction::SkipRegistersNotEqual(..) | Instruction::SkipKeyPressed(_) | Instruction::SkipKeyNotPressed(_) => 1,
+        Instruction::Set(..) | Instruction::Move(..) => 2,
+        Instruction::Add(..) | Instruction::Or(..) | Instruction::And(..) | Instruction::Xor(..) | Instruction::AddRegisters(..)
+        | Instruction::Subtract(..) | Instruction::ShiftRight(..) | Instruction::SubtractReverse(..) | Instruction::ShiftLeft(..) => 3,
+        Instruction::Random(..) => 4,
+        Instruction::SetIndex(_) | Instruction::AddIndex(_) | Instruction::FontCharacter(_) => 5,
+        Instruction::StoreBcd(_) | Instruction::StoreRegisters(_) | Instruction::LoadRegisters(_) => 6,
+        Instruction::Clear | Instruction::Draw(..) => 7,
+        Instruction::GetDelay(_) | Instruction::SetDelay(_) | Instruction::SetSound(_) => 8,
+        Instruction::WaitKey(_) => 9,
+    }
+}
+
+pub(crate) struct Profile {
+    counts: [u64; CLASSES.len()],
+    times: [Duration; CLASSES.len()],
+    // what reading the clock around an instruction costs by itself
+    overhead: Duration,
+}
+impl Profile {
+    fn new() -> Self {
+        let overhead = (0..CALIBRATION_ROUNDS)
+            .map(|_| {
+                let start = Instant::now();
+                for _ in 0..CALIBRATION_SAMPLES {
+                    hint::black_box(Instant::now().elapsed());
+                }
+                start.elapsed() / CALIBRATION_SAMPLES
+            })
+            .min()
+            .unwrap_or_default();
+        Self {
+            counts: [0; CLASSES.len()],
+            times: [Duration::ZERO; CLASSES.len()],
+            overhead,
+        }
+    }
+    pub(crate) fn record(&mut self, instruction: Instruction, elapsed: Duration) {
+        let class = class(instruction);
+        self.counts[class] += 1;
+        self.times[class] += elapsed;
+    }
+    fn time(&self, class: usize) -> Duration {
+        self.times[class].saturating_sub(self.overhead.mul_f64(self.counts[class] as f64))
+    }
+}
+
+pub struct BenchReport {
+    frames: u64,
+    instructions: u64,
+    emulation: Duration,
+    display: Duration,
+    profile: Profile,
+}
+// the rates only count time spent emulating, so they compare engines and processor
+// changes without the display work mixed in
+impl BenchReport {
+    pub fn instructions_per_second(&self) -> f64 {
+        self.instructions as f64 / self.emulation.as_secs_f64()
+    }
+    pub fn frames_per_second(&self) -> f64 {
+        self.frames as f64 / self.emulation.as_secs_f64()
+    }
+}
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "frames          {}", self.frames)?;
+        writeln!(f, "instructions    {}", self.instructions)?;
+        writeln!(f, "emulation       {:.3?}", self.emulation)?;
+        writeln!(f, "instructions/s  {:.0}", self.instructions_per_second())?;
+        writeln!(f, "frames/s        {:.0}", self.frames_per_second())?;
+        writeln!(f, "display         {:.3?}, {:.3?} per frame", self.display, self.display.div_f64(self.frames.max(1) as f64))?;
+        writeln!(f)?;
+        // instructions cheaper than reading the clock come out as zero
+        writeln!(f, "timed on the interpreter, less {:.1}ns of clock overhead per instruction", self.profile.overhead.as_nanos() as f64)?;
+        writeln!(f, "class         instructions     time  share  ns/instruction")?;
+        let total: Duration = (0..CLASSES.len()).map(|class| self.profile.time(class)).sum();
+        for (class, name) in CLASSES.iter().enumerate() {
+            let count = self.profile.counts[class];
+            if count == 0 {
+                continue;
+            }
+            let time = self.profile.time(class);
+            writeln!(f, "{:<12} {:>13} {:>7.3}s {:>5.1}% {:>15.1}",
+                name,
+                count,
+                time.as_secs_f64(),
+                100.0 * time.as_secs_f64() / total.as_secs_f64().max(f64::MIN_POSITIVE),
+                time.as_nanos() as f64 / count as f64,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// the first run measures throughput with the configured engine, including the cpu side of
+// presenting each frame, and a second run of the same frames times every instruction on
+// the interpreter, which is too slow to count towards throughput
+pub fn run_bench(rom: &Rom, config: &Config, frames: u64) -> Result<BenchReport, RunError> {
+    rom.check(config.start_address)?;
+    let config = Config {
+        seed: Some(config.seed.unwrap_or_else(rand::random)),
+        ..config.clone()
+    };
+
+    let (_input_tx, input_rx) = input::input(config.hold_taps);
+    let mut state = ProcessorState::new(input_rx, rom.data(), &config)?;
+    let mut persistence = Persistence::new(config.persistence, config.persistence_frames);
+    let mut presented = Framebuffer::new();
+    let mut color_indices = Vec::new();
+    let (mut emulation, mut display) = (Duration::ZERO, Duration::ZERO);
+    for _ in 0..frames {
+        let start = Instant::now();
+        state.run_frame(start)?;
+        let emulated = Instant::now();
+        let frame = state.take_frame();
+        persistence.update(&frame.framebuffer);
+        for rows in frame.framebuffer.dirty_rows() {
+            frame.framebuffer.color_indices(rows, &mut color_indices);
+        }
+        presented.clone_from(&frame.framebuffer);
+        display += emulated.elapsed();
+        emulation += emulated - start;
+    }
+
+    let (_input_tx, input_rx) = input::input(config.hold_taps);
+    let mut reference = ProcessorState::new(input_rx, rom.data(), &Config {
+        trace: None,
+        ..config
+    })?;
+    let mut profile = Profile::new();
+    for _ in 0..frames {
+        reference.run_frame_profiled(Instant::now(), &mut profile)?;
+    }
+
+    Ok(BenchReport {
+        frames,
+        instructions: state.instructions(),
+        emulation,
+        display,
+        profile,
+    })
+}
@@ -0,0 +1,131 @@
+use std::{fmt, fs, io, ops::RangeInclusive, path::{Path, PathBuf}};
+use serde::Deserialize;
+use crate::{quirks::{QuirkPreset, Quirks, EdgeMode}, palette::Palette, persistence::PersistenceMode, crt::CrtConfig, recording::RecordingFormat, display::{Backend, PresentMode}, terminal::TerminalRendering, timing::Timing, processor::Engine};
+
+// how far fast-forward and slow motion may scale the frame rate
+pub const SPEED_MULTIPLIERS: RangeInclusive<f64> = 0.01..=100.0;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub speed: u32,
+    pub timing: Timing,
+    pub engine: Engine,
+    pub quirks: QuirkPreset,
+    pub horizontal_edge: Option<EdgeMode>,
+    pub vertical_edge: Option<EdgeMode>,
+    pub display_wait: Option<bool>,
+    pub start_address: u16,
+    pub palette: Palette,
+    pub persistence: PersistenceMode,
+    pub persistence_frames: u32,
+    pub crt: CrtConfig,
+    pub shaders: Vec<PathBuf>,
+    pub capture_scale: u32,
+    pub capture_directory: PathBuf,
+    pub recording_format: RecordingFormat,
+    pub scale: u32,
+    pub seed: Option<u64>,
+    pub fullscreen: bool,
+    pub integer_scaling: bool,
+    pub backend: Backend,
+    pub present_mode: PresentMode,
+    pub terminal_rendering: TerminalRendering,
+    pub mute: bool,
+    pub hold_taps: bool,
+    pub fast_forward: f64,
+    pub slow_motion: f64,
+    pub pause_on_focus_loss: bool,
+    pub trace: Option<PathBuf>,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            speed: 12,
+            timing: Timing::default(),
+            engine: Engine::default(),
+            quirks: QuirkPreset::default(),
+            horizontal_edge: None,
+            vertical_edge: None,
+            display_wait: None,
+            start_address: 0x200,
+            palette: Palette::default(),
+            persistence: PersistenceMode::default(),
+            persistence_frames: 8,
+            crt: CrtConfig::default(),
+            shaders: Vec::new(),
+            capture_scale: 8,
+            capture_directory: PathBuf::from("."),
+            recording_format: RecordingFormat::default(),
+            scale: 10,
+            seed: None,
+            fullscreen: false,
+            integer_scaling: false,
+            backend: Backend::default(),
+            present_mode: PresentMode::default(),
+            terminal_rendering: TerminalRendering::default(),
+            mute: false,
+            hold_taps: true,
+            fast_forward: 4.0,
+            slow_motion: 0.25,
+            pause_on_focus_loss: false,
+            trace: None,
+        }
+    }
+}
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let source = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        toml::from_str(&source).map_err(ConfigError::Parse)
+    }
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let positive = [
+            ("speed", self.speed),
+            ("scale", self.scale),
+            ("capture_scale", self.capture_scale),
+            ("persistence_frames", self.persistence_frames),
+        ];
+        for (option, value) in positive {
+            if value == 0 {
+                return Err(ConfigError::Invalid { option, requirement: "at least 1".to_owned() });
+            }
+        }
+        for (option, value) in [("fast_forward", self.fast_forward), ("slow_motion", self.slow_motion)] {
+            if !SPEED_MULTIPLIERS.contains(&value) {
+                let requirement = format!("between {} and {}", SPEED_MULTIPLIERS.start(), SPEED_MULTIPLIERS.end());
+                return Err(ConfigError::Invalid { option, requirement });
+            }
+        }
+        Ok(())
+    }
+    pub fn quirks(&self) -> Quirks {
+        let mut quirks = Quirks::from(self.quirks);
+        if let Some(horizontal_edge) = self.horizontal_edge {
+            quirks.horizontal_edge = horizontal_edge;
+        }
+        if let Some(vertical_edge) = self.vertical_edge {
+            quirks.vertical_edge = vertical_edge;
+        }
+        if let Some(display_wait) = self.display_wait {
+            quirks.display_wait = display_wait;
+        }
+        quirks
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Invalid { option: &'static str, requirement: String },
+}
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(error) => write!(f, "{}", error),
+            ConfigError::Parse(error) => write!(f, "{}", error),
+            ConfigError::Invalid { option, requirement } => write!(f, "{} must be {}", option, requirement),
+        }
+    }
+}
+impl std::error::Error for ConfigError {}
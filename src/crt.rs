@@ -0,0 +1,55 @@
+use serde::Deserialize;
+use wgpu::SurfaceConfiguration;
+use winit::dpi::PhysicalSize;
+use crate::display;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CrtConfig {
+    pub enabled: bool,
+    pub scanlines: f32,
+    pub curvature: f32,
+    pub bloom: f32,
+    pub mask: f32,
+}
+impl Default for CrtConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            scanlines: 0.4,
+            curvature: 0.05,
+            bloom: 0.3,
+            mask: 0.2,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CrtUniform {
+    resolution: [f32; 2],
+    source_size: [f32; 2],
+    // x, y, width and height of the letterboxed image within the target
+    viewport: [f32; 4],
+    scanlines: f32,
+    curvature: f32,
+    bloom: f32,
+    mask: f32,
+}
+
+pub fn uniform(surface_config: &SurfaceConfiguration, source_size: [f32; 2], integer_scaling: bool, config: &CrtConfig) -> CrtUniform {
+    let (x, y, width, height) = display::viewport(
+        PhysicalSize::new(surface_config.width, surface_config.height),
+        (source_size[0] as u32, source_size[1] as u32),
+        integer_scaling,
+    );
+    CrtUniform {
+        resolution: [surface_config.width as f32, surface_config.height as f32],
+        source_size,
+        viewport: [x, y, width, height],
+        scanlines: config.scanlines,
+        curvature: config.curvature,
+        bloom: config.bloom,
+        mask: config.mask,
+    }
+}
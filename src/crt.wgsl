@@ -0,0 +1,80 @@
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main(
+    [[builtin(vertex_index)]] vertex_index: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    out.uv = uv;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    return out;
+}
+
+struct CrtParams {
+    resolution: vec2<f32>;
+    source_size: vec2<f32>;
+    viewport: vec4<f32>;
+    scanlines: f32;
+    curvature: f32;
+    bloom: f32;
+    mask: f32;
+};
+
+[[group(0), binding(0)]]
+var source: texture_2d<f32>;
+[[group(0), binding(1)]]
+var source_sampler: sampler;
+[[group(0), binding(2)]]
+var<uniform> params: CrtParams;
+
+fn curve(uv: vec2<f32>) -> vec2<f32> {
+    let centered = uv * 2.0 - 1.0;
+    let offset = centered.yx * centered.yx * params.curvature;
+    return (centered + centered * offset) * 0.5 + 0.5;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    // uv covers the letterboxed image, while the source texture covers the whole target
+    let uv = curve((in.uv * params.resolution - params.viewport.xy) / params.viewport.zw);
+    let source_uv = (params.viewport.xy + uv * params.viewport.zw) / params.resolution;
+
+    var color = textureSample(source, source_sampler, source_uv).rgb;
+
+    let texel = params.viewport.zw / params.source_size / params.resolution;
+    var glow = vec3<f32>(0.0, 0.0, 0.0);
+    glow = glow + textureSample(source, source_sampler, source_uv + vec2<f32>(texel.x, 0.0)).rgb;
+    glow = glow + textureSample(source, source_sampler, source_uv - vec2<f32>(texel.x, 0.0)).rgb;
+    glow = glow + textureSample(source, source_sampler, source_uv + vec2<f32>(0.0, texel.y)).rgb;
+    glow = glow + textureSample(source, source_sampler, source_uv - vec2<f32>(0.0, texel.y)).rgb;
+    glow = glow + textureSample(source, source_sampler, source_uv + texel).rgb;
+    glow = glow + textureSample(source, source_sampler, source_uv - texel).rgb;
+    glow = glow + textureSample(source, source_sampler, source_uv + vec2<f32>(texel.x, -texel.y)).rgb;
+    glow = glow + textureSample(source, source_sampler, source_uv + vec2<f32>(-texel.x, texel.y)).rgb;
+    color = color + glow / 8.0 * params.bloom;
+
+    let row = fract(uv.y * params.source_size.y);
+    let scanline = 1.0 - params.scanlines * pow(abs(row * 2.0 - 1.0), 2.0);
+    color = color * scanline;
+
+    let column = u32(in.clip_position.x) % 3u;
+    var mask = vec3<f32>(1.0, 1.0, 1.0);
+    if (column == 0u) {
+        mask = vec3<f32>(1.0, 1.0 - params.mask, 1.0 - params.mask);
+    } else if (column == 1u) {
+        mask = vec3<f32>(1.0 - params.mask, 1.0, 1.0 - params.mask);
+    } else {
+        mask = vec3<f32>(1.0 - params.mask, 1.0 - params.mask, 1.0);
+    }
+    color = color * mask;
+
+    // samples are taken before this branch, as they require uniform control flow
+    if (uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+    return vec4<f32>(color, 1.0);
+}
@@ -0,0 +1,244 @@
+use std::ops::Range;
+use crate::quirks::EdgeMode;
+
+pub const DISPLAY_WIDTH: usize = 64;
+pub const DISPLAY_HEIGHT: usize = 32;
+pub const MAX_PLANES: usize = 2;
+
+const WORD_BITS: usize = u64::BITS as usize;
+
+#[derive(Clone)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    planes: usize,
+    words_per_row: usize,
+    words: Vec<u64>,
+    dirty: Vec<bool>,
+}
+impl Framebuffer {
+    pub fn new() -> Self {
+        Self::with_size(DISPLAY_WIDTH, DISPLAY_HEIGHT, 1)
+    }
+    pub fn with_size(width: usize, height: usize, planes: usize) -> Self {
+        let width = width.max(1);
+        let height = height.max(1);
+        let planes = planes.clamp(1, MAX_PLANES);
+        let words_per_row = width.div_ceil(WORD_BITS);
+        Self {
+            width,
+            height,
+            planes,
+            words_per_row,
+            words: vec![0; planes * height * words_per_row],
+            dirty: vec![true; height],
+        }
+    }
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+    pub fn planes(&self) -> usize {
+        self.planes
+    }
+    fn bit(&self, plane: usize, x: usize, y: usize) -> (usize, u64) {
+        let index = (plane * self.height + y) * self.words_per_row + x / WORD_BITS;
+        (index, 1 << (WORD_BITS - 1 - x % WORD_BITS))
+    }
+    pub fn color_index(&self, x: usize, y: usize) -> u8 {
+        (0..self.planes).fold(0, |index, plane| {
+            let (word, mask) = self.bit(plane, x, y);
+            index | (((self.words[word] & mask) != 0) as u8) << plane
+        })
+    }
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.color_index(x, y) != 0
+    }
+    pub fn color_indices(&self, rows: Range<usize>, indices: &mut Vec<u8>) {
+        indices.clear();
+        indices.reserve(self.width * rows.len());
+        for y in rows {
+            for x in 0..self.width {
+                indices.push(self.color_index(x, y));
+            }
+        }
+    }
+    pub fn draw(&mut self, x: usize, y: usize, sprite: &[u8], horizontal_edge: EdgeMode, vertical_edge: EdgeMode) -> bool {
+        self.draw_plane(0, x, y, sprite, horizontal_edge, vertical_edge)
+    }
+    pub fn draw_plane(&mut self, plane: usize, x: usize, y: usize, sprite: &[u8], horizontal_edge: EdgeMode, vertical_edge: EdgeMode) -> bool {
+        let mut flip = false;
+        let x = x % self.width;
+        let y = y % self.height;
+        let visible = self.width - x;
+        for (dy, row) in sprite.iter().enumerate() {
+            let row_y = match (y + dy, vertical_edge) {
+                (row_y, _) if row_y < self.height => row_y,
+                (_, EdgeMode::Clip) => break,
+                (row_y, EdgeMode::Wrap) => row_y % self.height,
+            };
+            let bits = (*row as u64) << (WORD_BITS - 8);
+            let inside = match visible {
+                visible if visible >= WORD_BITS => bits,
+                visible => bits & !(u64::MAX >> visible),
+            };
+            flip |= self.xor_row(plane, x, row_y, inside);
+            if horizontal_edge == EdgeMode::Wrap && visible < 8 {
+                flip |= self.xor_row(plane, 0, row_y, bits << visible);
+            }
+        }
+        flip
+    }
+    // xors up to a word of pixels starting at x, given left aligned, into the row,
+    // touching at most the two words they straddle
+    fn xor_row(&mut self, plane: usize, x: usize, y: usize, bits: u64) -> bool {
+        let row = (plane * self.height + y) * self.words_per_row + x / WORD_BITS;
+        let offset = x % WORD_BITS;
+        let mut flip = false;
+        let parts = [
+            bits >> offset,
+            if offset == 0 { 0 } else { bits << (WORD_BITS - offset) },
+        ];
+        for (index, part) in parts.into_iter().enumerate() {
+            if part != 0 {
+                self.dirty[y] = true;
+                let word = &mut self.words[row + index];
+                flip |= *word & part != 0;
+                *word ^= part;
+            }
+        }
+        flip
+    }
+    pub fn clear(&mut self) {
+        self.words.fill(0);
+        self.dirty.fill(true);
+    }
+    // runs of rows touched by draw or clear since the last clear_dirty
+    pub fn dirty_rows(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+        let mut y = 0;
+        std::iter::from_fn(move || {
+            let start = y + self.dirty[y..].iter().position(|dirty| *dirty)?;
+            let end = start + self.dirty[start..].iter().position(|dirty| !dirty).unwrap_or(self.height - start);
+            y = end;
+            Some(start..end)
+        })
+    }
+    pub fn clear_dirty(&mut self) {
+        self.dirty.fill(false);
+    }
+    pub fn merge_dirty(&mut self, other: &Framebuffer) {
+        if other.height == self.height {
+            self.dirty.iter_mut().zip(&other.dirty).for_each(|(dirty, other)| *dirty |= other);
+        } else {
+            self.dirty.fill(true);
+        }
+    }
+}
+impl PartialEq for Framebuffer {
+    fn eq(&self, other: &Self) -> bool {
+        (self.width, self.height, self.planes) == (other.width, other.height, other.planes) && self.words == other.words
+    }
+}
+impl Eq for Framebuffer {}
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::EdgeMode::{Clip, Wrap};
+
+    fn lit(framebuffer: &Framebuffer, y: usize) -> Vec<usize> {
+        (0..framebuffer.width()).filter(|&x| framebuffer.pixel(x, y)).collect()
+    }
+
+    #[test]
+    fn right_edge_clips_without_bleeding_onto_the_next_row() {
+        let mut framebuffer = Framebuffer::new();
+        assert!(!framebuffer.draw(60, 0, &[0xFF], Clip, Clip));
+        assert_eq!(lit(&framebuffer, 0), [60, 61, 62, 63]);
+        assert_eq!(lit(&framebuffer, 1), [] as [usize; 0]);
+    }
+
+    #[test]
+    fn right_edge_wraps_onto_the_same_row() {
+        let mut framebuffer = Framebuffer::new();
+        assert!(!framebuffer.draw(60, 0, &[0xFF], Wrap, Clip));
+        assert_eq!(lit(&framebuffer, 0), [0, 1, 2, 3, 60, 61, 62, 63]);
+        assert_eq!(lit(&framebuffer, 1), [] as [usize; 0]);
+    }
+
+    #[test]
+    fn bottom_edge_clips_or_wraps() {
+        let sprite = [0x80; 8];
+        let mut clipped = Framebuffer::new();
+        clipped.draw(0, 30, &sprite, Clip, Clip);
+        let rows: Vec<usize> = (0..DISPLAY_HEIGHT).filter(|&y| clipped.pixel(0, y)).collect();
+        assert_eq!(rows, [30, 31]);
+
+        let mut wrapped = Framebuffer::new();
+        wrapped.draw(0, 30, &sprite, Clip, Wrap);
+        let rows: Vec<usize> = (0..DISPLAY_HEIGHT).filter(|&y| wrapped.pixel(0, y)).collect();
+        assert_eq!(rows, [0, 1, 2, 3, 4, 5, 30, 31]);
+    }
+
+    #[test]
+    fn corner_wraps_on_both_axes() {
+        let mut framebuffer = Framebuffer::new();
+        framebuffer.draw(60, 30, &[0xFF; 8], Wrap, Wrap);
+        for y in [30, 31, 0, 1, 2, 3, 4, 5] {
+            assert_eq!(lit(&framebuffer, y), [0, 1, 2, 3, 60, 61, 62, 63], "row {}", y);
+        }
+        for y in 6..30 {
+            assert_eq!(lit(&framebuffer, y), [] as [usize; 0], "row {}", y);
+        }
+
+        let mut clipped = Framebuffer::new();
+        clipped.draw(60, 30, &[0xFF; 8], Clip, Clip);
+        assert_eq!(lit(&clipped, 30), [60, 61, 62, 63]);
+        assert_eq!(lit(&clipped, 0), [] as [usize; 0]);
+    }
+
+    #[test]
+    fn collisions_are_reported_across_the_wrapped_part() {
+        let mut framebuffer = Framebuffer::new();
+        framebuffer.draw(0, 0, &[0x80], Clip, Clip);
+        // the clipped part never reaches the pixel at the left edge
+        assert!(!framebuffer.draw(60, 0, &[0xFF], Clip, Clip));
+        framebuffer.draw(60, 0, &[0xFF], Clip, Clip);
+        // only the wrapped part overlaps something now
+        assert!(framebuffer.draw(60, 0, &[0xFF], Wrap, Clip));
+        assert_eq!(lit(&framebuffer, 0), [1, 2, 3, 60, 61, 62, 63]);
+
+        let mut framebuffer = Framebuffer::new();
+        framebuffer.draw(0, 0, &[0x80], Clip, Clip);
+        assert!(framebuffer.draw(0, 30, &[0x80; 3], Clip, Wrap));
+        assert!(!framebuffer.pixel(0, 0));
+    }
+
+    #[test]
+    fn width_that_is_not_a_multiple_of_a_word() {
+        let mut framebuffer = Framebuffer::with_size(100, 50, 1);
+        framebuffer.draw(60, 0, &[0xFF], Clip, Clip);
+        assert_eq!(lit(&framebuffer, 0), (60..68).collect::<Vec<_>>());
+
+        framebuffer.draw(96, 1, &[0xFF], Clip, Clip);
+        assert_eq!(lit(&framebuffer, 1), [96, 97, 98, 99]);
+        assert_eq!(lit(&framebuffer, 2), [] as [usize; 0]);
+
+        framebuffer.draw(96, 3, &[0xFF], Wrap, Clip);
+        assert_eq!(lit(&framebuffer, 3), [0, 1, 2, 3, 96, 97, 98, 99]);
+        assert!(framebuffer.draw(96, 3, &[0xFF], Wrap, Clip));
+        assert_eq!(lit(&framebuffer, 3), [] as [usize; 0]);
+
+        let mut indices = Vec::new();
+        framebuffer.color_indices(0..2, &mut indices);
+        assert_eq!(indices.len(), 200);
+        assert_eq!(indices.iter().filter(|&&index| index != 0).count(), 12);
+    }
+}
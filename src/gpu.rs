@@ -0,0 +1,437 @@
+use std::ops::Range;
+use winit::{window::Window, dpi::PhysicalSize};
+use wgpu::*;
+use crate::{framebuffer::{Framebuffer, DISPLAY_WIDTH, DISPLAY_HEIGHT}, palette::Palette, post_process::PostProcess, config::Config, display::{self, DisplayError}};
+
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct Vertex {
+    position: [f32; 3],
+    tex_coords: [f32; 2],
+}
+impl Vertex {
+    fn desc<'a>() -> VertexBufferLayout<'a> {
+        VertexBufferLayout {
+            array_stride: std::mem::size_of::<Vertex>() as BufferAddress,
+            step_mode: VertexStepMode::Vertex,
+            attributes: &[
+                VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: VertexFormat::Float32x3,
+                },
+                VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3]>() as BufferAddress,
+                    shader_location: 1,
+                    format: VertexFormat::Float32x2,
+                }
+            ]
+        }
+    }
+}
+
+const VERTICES: &[Vertex] = &[
+    Vertex { position: [-1.0, -1.0, 0.0], tex_coords: [0.0, 1.0] },
+    Vertex { position: [1.0, -1.0, 0.0], tex_coords: [1.0, 1.0] },
+    Vertex { position: [1.0, 1.0, 0.0], tex_coords: [1.0, 0.0] },
+    Vertex { position: [-1.0, 1.0, 0.0], tex_coords: [0.0, 0.0] },
+];
+const INDICES: &[u16] = &[
+    0, 1, 2,
+    0, 2, 3,
+];
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct PaletteUniform {
+    colors: [[f32; 4]; 4],
+}
+impl From<Palette> for PaletteUniform {
+    fn from(palette: Palette) -> Self {
+        Self {
+            colors: palette.colors.map(|color| color.to_linear()),
+        }
+    }
+}
+
+pub struct GpuDisplay {
+    surface: Surface,
+    device: Device,
+    queue: Queue,
+    config: SurfaceConfiguration,
+    size: PhysicalSize<u32>,
+    framebuffer_size: (u32, u32),
+    color_indices: Vec<u8>,
+    texture: Texture,
+    persistence_texture: Texture,
+    texture_bind_group_layout: BindGroupLayout,
+    texture_bind_group: BindGroup,
+    palette: PaletteUniform,
+    palette_buffer: Buffer,
+    render_pipeline: RenderPipeline,
+    post_process: PostProcess,
+    integer_scaling: bool,
+    vertex_buffer: Buffer,
+    index_buffer: Buffer,
+}
+impl GpuDisplay {
+    pub async fn new(window: &Window, options: &Config) -> Result<Self, DisplayError> {
+        let framebuffer_size = (DISPLAY_WIDTH as u32, DISPLAY_HEIGHT as u32);
+        let palette = PaletteUniform::from(options.palette);
+
+        let size = window.inner_size();
+        let instance = Instance::new(Backends::all());
+        let surface = unsafe { instance.create_surface(window) };
+        let adapter = instance.request_adapter(
+            &RequestAdapterOptions {
+                power_preference: PowerPreference::default(),
+                compatible_surface: Some(&surface),
+                force_fallback_adapter: false,
+            },
+        ).await.ok_or(DisplayError::NoAdapter)?;
+        let (device, queue) = adapter.request_device(
+            &DeviceDescriptor {
+                features: Features::empty(),
+                limits: if cfg!(target_arch = "wasm32") {
+                    Limits::downlevel_webgl2_defaults()
+                } else {
+                    Limits::default()
+                },
+                label: None,
+            },
+            None,
+        ).await.map_err(DisplayError::RequestDevice)?;
+        let config = SurfaceConfiguration {
+            usage: TextureUsages::RENDER_ATTACHMENT,
+            format: surface.get_preferred_format(&adapter).ok_or(DisplayError::UnsupportedSurface)?,
+            width: size.width,
+            height: size.height,
+            present_mode: options.present_mode.into(),
+        };
+        surface.configure(&device, &config);
+
+        let post_process = PostProcess::new(&device, &config, [framebuffer_size.0 as f32, framebuffer_size.1 as f32], options.integer_scaling, options.crt, &options.shaders);
+
+        use util::DeviceExt;
+
+        let palette_buffer = device.create_buffer_init(
+            &util::BufferInitDescriptor {
+                label: Some("palette_buffer"),
+                contents: bytemuck::bytes_of(&palette),
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            }
+        );
+
+        let texture_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: TextureViewDimension::D2,
+                        sample_type: TextureSampleType::Uint,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: TextureViewDimension::D2,
+                        sample_type: TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("texture_bind_group_layout"),
+        });
+        let (texture, persistence_texture, texture_bind_group) = Self::create_framebuffer_textures(&device, &texture_bind_group_layout, &palette_buffer, framebuffer_size);
+
+        let shader = device.create_shader_module(&ShaderModuleDescriptor {
+            label: Some("shader"),
+            source: ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
+        });
+        let render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("render_pipeline_layout"),
+            bind_group_layouts: &[
+                &texture_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("render_pipeline"),
+            layout: Some(&render_pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[
+                    Vertex::desc(),
+                ],
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[ColorTargetState {
+                    format: config.format,
+                    blend: Some(BlendState::REPLACE),
+                    write_mask: ColorWrites::ALL,
+                }],
+            }),
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Ccw,
+                cull_mode: Some(Face::Back),
+                polygon_mode: PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        let vertex_buffer = device.create_buffer_init(
+            &util::BufferInitDescriptor {
+                label: Some("vertex_buffer"),
+                contents: bytemuck::cast_slice(VERTICES),
+                usage: BufferUsages::VERTEX,
+            }
+        );
+        let index_buffer = device.create_buffer_init(
+            &util::BufferInitDescriptor {
+                label: Some("index_buffer"),
+                contents: bytemuck::cast_slice(INDICES),
+                usage: BufferUsages::INDEX,
+            }
+        ); 
+
+        Ok(Self {
+            surface,
+            device,
+            queue,
+            config,
+            size,
+            framebuffer_size,
+            color_indices: Vec::new(),
+            texture,
+            persistence_texture,
+            texture_bind_group_layout,
+            texture_bind_group,
+            palette,
+            palette_buffer,
+            render_pipeline,
+            post_process,
+            integer_scaling: options.integer_scaling,
+            vertex_buffer,
+            index_buffer,
+        })
+    }
+    pub fn size(&self) -> PhysicalSize<u32> {
+        self.size
+    }
+    fn create_framebuffer_textures(device: &Device, layout: &BindGroupLayout, palette_buffer: &Buffer, (width, height): (u32, u32)) -> (Texture, Texture, BindGroup) {
+        let size = Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(
+            &TextureDescriptor {
+                label: Some("texture"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::R8Uint,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            }
+        );
+        let texture_view = texture.create_view(&TextureViewDescriptor::default());
+
+        let persistence_texture = device.create_texture(
+            &TextureDescriptor {
+                label: Some("persistence_texture"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::R8Unorm,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            }
+        );
+        let persistence_texture_view = persistence_texture.create_view(&TextureViewDescriptor::default());
+
+        let texture_bind_group = device.create_bind_group(
+            &BindGroupDescriptor {
+                layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(&texture_view),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: palette_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: BindingResource::TextureView(&persistence_texture_view),
+                    },
+                ],
+                label: Some("texture_bind_group"),
+            }
+        );
+        (texture, persistence_texture, texture_bind_group)
+    }
+    pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        if new_size.width > 0 && new_size.height > 0 {
+            self.size = new_size;
+            self.config.width = new_size.width;
+            self.config.height = new_size.height;
+            self.surface.configure(&self.device, &self.config);
+            self.post_process.resize(&self.device, &self.queue, &self.config);
+        }
+    }
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = PaletteUniform::from(palette);
+        self.queue.write_buffer(&self.palette_buffer, 0, bytemuck::bytes_of(&self.palette));
+    }
+    pub fn update(&mut self, framebuffer: &Framebuffer, intensities: &[u8]) {
+        let framebuffer_size = (framebuffer.width() as u32, framebuffer.height() as u32);
+        let resized = framebuffer_size != self.framebuffer_size;
+        if resized {
+            self.framebuffer_size = framebuffer_size;
+            (self.texture, self.persistence_texture, self.texture_bind_group) = Self::create_framebuffer_textures(&self.device, &self.texture_bind_group_layout, &self.palette_buffer, framebuffer_size);
+            self.post_process.set_source_size(&self.queue, &self.config, [framebuffer_size.0 as f32, framebuffer_size.1 as f32]);
+        }
+
+        if resized {
+            self.write_rows(framebuffer, 0..framebuffer.height());
+        } else {
+            for rows in framebuffer.dirty_rows() {
+                self.write_rows(framebuffer, rows);
+            }
+        }
+
+        if intensities.len() == framebuffer.width() * framebuffer.height() {
+            let (width, height) = framebuffer_size;
+            self.queue.write_texture(
+                ImageCopyTexture {
+                    aspect: TextureAspect::All,
+                    texture: &self.persistence_texture,
+                    mip_level: 0,
+                    origin: Origin3d::ZERO,
+                },
+                intensities,
+                ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(width),
+                    rows_per_image: std::num::NonZeroU32::new(height),
+                },
+                Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+    }
+    fn write_rows(&mut self, framebuffer: &Framebuffer, rows: Range<usize>) {
+        let width = self.framebuffer_size.0;
+        let height = rows.len() as u32;
+        framebuffer.color_indices(rows.clone(), &mut self.color_indices);
+        self.queue.write_texture(
+            ImageCopyTexture {
+                aspect: TextureAspect::All,
+                texture: &self.texture,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: 0,
+                    y: rows.start as u32,
+                    z: 0,
+                },
+            },
+            &self.color_indices,
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(width),
+                rows_per_image: std::num::NonZeroU32::new(height),
+            },
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+    pub fn animating(&self) -> bool {
+        self.post_process.animated()
+    }
+    pub fn render(&mut self) -> Result<(), SurfaceError> {
+        let output = self.surface.get_current_texture()?;
+        let output_view = output.texture.create_view(&TextureViewDescriptor::default());
+        let target_view = if self.post_process.enabled() {
+            self.post_process.target_view()
+        } else {
+            &output_view
+        };
+        let mut render_encoder = self.device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("render_encoder"),
+        });
+
+        {
+            let mut render_pass = render_encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("render_pass"),
+                color_attachments: &[RenderPassColorAttachment {
+                    view: target_view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(Color {
+                            r: self.palette.colors[0][0] as f64,
+                            g: self.palette.colors[0][1] as f64,
+                            b: self.palette.colors[0][2] as f64,
+                            a: 1.0,
+                        }),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+
+            let (x, y, width, height) = display::viewport(self.size, self.framebuffer_size, self.integer_scaling);
+            render_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+            render_pass.set_pipeline(&self.render_pipeline);
+            render_pass.set_bind_group(0, &self.texture_bind_group, &[]);
+            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(self.index_buffer.slice(..), IndexFormat::Uint16);
+            render_pass.draw_indexed(0..INDICES.len() as u32, 0, 0..1);
+        }
+
+        if self.post_process.enabled() {
+            self.post_process.render(&self.device, &self.queue, &mut render_encoder, &output_view, self.palette.colors);
+        }
+
+        self.queue.submit(std::iter::once(render_encoder.finish()));
+        output.present();
+
+        Ok(())
+    }
+}
@@ -1,8 +1,8 @@
-use std::sync::mpsc::{self, Receiver, SyncSender, Sender, SendError, TrySendError, RecvError};
+use std::{sync::mpsc::{self, Receiver, Sender, SendError}, collections::VecDeque, time::Instant};
 use winit::event::*;
 
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Key {
     Key0,
     Key1,
@@ -21,224 +21,223 @@ pub enum Key {
     KeyE,
     KeyF,
 }
+impl Key {
+    pub const ALL: [Key; 16] = [
+        Key::Key0, Key::Key1, Key::Key2, Key::Key3,
+        Key::Key4, Key::Key5, Key::Key6, Key::Key7,
+        Key::Key8, Key::Key9, Key::KeyA, Key::KeyB,
+        Key::KeyC, Key::KeyD, Key::KeyE, Key::KeyF,
+    ];
+    pub fn from_keycode(keycode: VirtualKeyCode) -> Option<Self> {
+        match keycode {
+            VirtualKeyCode::X => Some(Key::Key0),
+            VirtualKeyCode::Key1 => Some(Key::Key1),
+            VirtualKeyCode::Key2 => Some(Key::Key2),
+            VirtualKeyCode::Key3 => Some(Key::Key3),
+            VirtualKeyCode::Q => Some(Key::Key4),
+            VirtualKeyCode::W => Some(Key::Key5),
+            VirtualKeyCode::E => Some(Key::Key6),
+            VirtualKeyCode::A => Some(Key::Key7),
+            VirtualKeyCode::S => Some(Key::Key8),
+            VirtualKeyCode::D => Some(Key::Key9),
+            VirtualKeyCode::Z => Some(Key::KeyA),
+            VirtualKeyCode::C => Some(Key::KeyB),
+            VirtualKeyCode::Key4 => Some(Key::KeyC),
+            VirtualKeyCode::R => Some(Key::KeyD),
+            VirtualKeyCode::F => Some(Key::KeyE),
+            VirtualKeyCode::V => Some(Key::KeyF),
+            _ => None,
+        }
+    }
+    pub fn from_char(character: char) -> Option<Self> {
+        match character.to_ascii_lowercase() {
+            'x' => Some(Key::Key0),
+            '1' => Some(Key::Key1),
+            '2' => Some(Key::Key2),
+            '3' => Some(Key::Key3),
+            'q' => Some(Key::Key4),
+            'w' => Some(Key::Key5),
+            'e' => Some(Key::Key6),
+            'a' => Some(Key::Key7),
+            's' => Some(Key::Key8),
+            'd' => Some(Key::Key9),
+            'z' => Some(Key::KeyA),
+            'c' => Some(Key::KeyB),
+            '4' => Some(Key::KeyC),
+            'r' => Some(Key::KeyD),
+            'f' => Some(Key::KeyE),
+            'v' => Some(Key::KeyF),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyState {
+    Pressed,
+    Released,
+}
+impl From<ElementState> for KeyState {
+    fn from(state: ElementState) -> Self {
+        match state {
+            ElementState::Pressed => KeyState::Pressed,
+            ElementState::Released => KeyState::Released,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct KeyEvent {
+    pub key: Key,
+    pub state: KeyState,
+    pub time: Instant,
+}
 
 pub struct InputSender {
-    key_tx: Sender<KeyboardInput>,
-    next_key_tx: SyncSender<KeyboardInput>,
+    key_tx: Sender<KeyEvent>,
 }
 impl InputSender {
-    pub fn send_key_event(&self, key_event: KeyboardInput) -> Result<(), SendError<KeyboardInput>> {
-        self.key_tx.send(key_event)?;
-        match self.next_key_tx.try_send(key_event) {
-            Err(TrySendError::Disconnected(key_event)) => Err(SendError(key_event)),
-            _ => Ok(())
+    pub fn send_key_event(&self, key_event: KeyboardInput) -> Result<(), SendError<KeyEvent>> {
+        if let Some(key) = key_event.virtual_keycode.and_then(Key::from_keycode) {
+            self.send_key(key, key_event.state.into())?;
         }
+        Ok(())
+    }
+    pub fn send_key(&self, key: Key, state: KeyState) -> Result<(), SendError<KeyEvent>> {
+        self.key_tx.send(KeyEvent {
+            key,
+            state,
+            time: Instant::now(),
+        })
     }
 }
 
 pub struct InputReceiver {
     key_states: u16,
-    key_rx: Receiver<KeyboardInput>,
-    next_key_rx: Receiver<KeyboardInput>,
+    frame_presses: u16,
+    held_releases: u16,
+    hold_taps: bool,
+    last_key_release: Option<Key>,
+    key_events: VecDeque<KeyEvent>,
+    key_rx: Receiver<KeyEvent>,
 }
 impl InputReceiver {
-    pub fn recv_key_press(&self) -> Result<Key, RecvError> {
-        for key_event in self.next_key_rx.iter() {
-            match key_event {
-                KeyboardInput {
-                    state: ElementState::Pressed,
-                    virtual_keycode: Some(keycode),
-                    ..
-                } => {
-                    match keycode {
-                        VirtualKeyCode::X => {
-                            return Ok(Key::Key0);
-                        },
-                        VirtualKeyCode::Key1 => {
-                            return Ok(Key::Key1);
-                        },
-                        VirtualKeyCode::Key2 => {
-                            return Ok(Key::Key2);
-                        },
-                        VirtualKeyCode::Key3 => {
-                            return Ok(Key::Key3);
-                        },
-                        VirtualKeyCode::Q => {
-                            return Ok(Key::Key4);
-                        },
-                        VirtualKeyCode::W => {
-                            return Ok(Key::Key5);
-                        },
-                        VirtualKeyCode::E => {
-                            return Ok(Key::Key6);
-                        },
-                        VirtualKeyCode::A => {
-                            return Ok(Key::Key7);
-                        },
-                        VirtualKeyCode::S => {
-                            return Ok(Key::Key8);
-                        },
-                        VirtualKeyCode::D => {
-                            return Ok(Key::Key9);
-                        },
-                        VirtualKeyCode::Z => {
-                            return Ok(Key::KeyA);
-                        },
-                        VirtualKeyCode::C => {
-                            return Ok(Key::KeyB);
-                        },
-                        VirtualKeyCode::Key4 => {
-                            return Ok(Key::KeyC);
-                        },
-                        VirtualKeyCode::R => {
-                            return Ok(Key::KeyD);
-                        },
-                        VirtualKeyCode::F => {
-                            return Ok(Key::KeyE);
-                        },
-                        VirtualKeyCode::V => {
-                            return Ok(Key::KeyF);
-                        },
-                        _ => {},
-                    }
-                },
-                _ => {},
+    pub fn process_key_events(&mut self, until: Instant) {
+        self.key_events.extend(self.key_rx.try_iter());
+        while let Some(key_event) = self.key_events.front() {
+            if key_event.time > until {
+                break;
             }
-        }
-        Err(RecvError)
-    }
-    pub fn process_key_events(&mut self) {
-        for key_event in self.key_rx.try_iter() {
-            match key_event {
-                KeyboardInput {
-                    state,
-                    virtual_keycode: Some(keycode),
-                    ..
-                } => {
-                    match keycode {
-                        VirtualKeyCode::X => {
-                            match state {
-                                ElementState::Pressed => self.key_states |= 1 << Key::Key0 as u8,
-                                ElementState::Released => self.key_states &= !(1 << Key::Key0 as u8),
-                            }
-                        },
-                        VirtualKeyCode::Key1 => {
-                            match state {
-                                ElementState::Pressed => self.key_states |= 1 << Key::Key1 as u8,
-                                ElementState::Released => self.key_states &= !(1 << Key::Key1 as u8),
-                            }
-                        },
-                        VirtualKeyCode::Key2 => {
-                            match state {
-                                ElementState::Pressed => self.key_states |= 1 << Key::Key2 as u8,
-                                ElementState::Released => self.key_states &= !(1 << Key::Key2 as u8),
-                            }
-                        },
-                        VirtualKeyCode::Key3 => {
-                            match state {
-                                ElementState::Pressed => self.key_states |= 1 << Key::Key3 as u8,
-                                ElementState::Released => self.key_states &= !(1 << Key::Key3 as u8),
-                            }
-                        },
-                        VirtualKeyCode::Q => {
-                            match state {
-                                ElementState::Pressed => self.key_states |= 1 << Key::Key4 as u8,
-                                ElementState::Released => self.key_states &= !(1 << Key::Key4 as u8),
-                            }
-                        },
-                        VirtualKeyCode::W => {
-                            match state {
-                                ElementState::Pressed => self.key_states |= 1 << Key::Key5 as u8,
-                                ElementState::Released => self.key_states &= !(1 << Key::Key5 as u8),
-                            }
-                        },
-                        VirtualKeyCode::E => {
-                            match state {
-                                ElementState::Pressed => self.key_states |= 1 << Key::Key6 as u8,
-                                ElementState::Released => self.key_states &= !(1 << Key::Key6 as u8),
-                            }
-                        },
-                        VirtualKeyCode::A => {
-                            match state {
-                                ElementState::Pressed => self.key_states |= 1 << Key::Key7 as u8,
-                                ElementState::Released => self.key_states &= !(1 << Key::Key7 as u8),
-                            }
-                        },
-                        VirtualKeyCode::S => {
-                            match state {
-                                ElementState::Pressed => self.key_states |= 1 << Key::Key8 as u8,
-                                ElementState::Released => self.key_states &= !(1 << Key::Key8 as u8),
-                            }
-                        },
-                        VirtualKeyCode::D => {
-                            match state {
-                                ElementState::Pressed => self.key_states |= 1 << Key::Key9 as u8,
-                                ElementState::Released => self.key_states &= !(1 << Key::Key9 as u8),
-                            }
-                        },
-                        VirtualKeyCode::Z => {
-                            match state {
-                                ElementState::Pressed => self.key_states |= 1 << Key::KeyA as u8,
-                                ElementState::Released => self.key_states &= !(1 << Key::KeyA as u8),
-                            }
-                        },
-                        VirtualKeyCode::C => {
-                            match state {
-                                ElementState::Pressed => self.key_states |= 1 << Key::KeyB as u8,
-                                ElementState::Released => self.key_states &= !(1 << Key::KeyB as u8),
-                            }
-                        },
-                        VirtualKeyCode::Key4 => {
-                            match state {
-                                ElementState::Pressed => self.key_states |= 1 << Key::KeyC as u8,
-                                ElementState::Released => self.key_states &= !(1 << Key::KeyC as u8),
-                            }
-                        },
-                        VirtualKeyCode::R => {
-                            match state {
-                                ElementState::Pressed => self.key_states |= 1 << Key::KeyD as u8,
-                                ElementState::Released => self.key_states &= !(1 << Key::KeyD as u8),
-                            }
-                        },
-                        VirtualKeyCode::F => {
-                            match state {
-                                ElementState::Pressed => self.key_states |= 1 << Key::KeyE as u8,
-                                ElementState::Released => self.key_states &= !(1 << Key::KeyE as u8),
-                            }
-                        },
-                        VirtualKeyCode::V => {
-                            match state {
-                                ElementState::Pressed => self.key_states |= 1 << Key::KeyF as u8,
-                                ElementState::Released => self.key_states &= !(1 << Key::KeyF as u8),
-                            }
-                        },
-                        _ => {},
+            let key_event = self.key_events.pop_front().unwrap();
+            let mask = 1 << key_event.key as u8;
+            match key_event.state {
+                KeyState::Pressed => {
+                    self.key_states |= mask;
+                    self.frame_presses |= mask;
+                    self.held_releases &= !mask;
+                },
+                KeyState::Released => {
+                    self.last_key_release = Some(key_event.key);
+                    // a key tapped within a single frame stays down until the frame ends,
+                    // so that it can still be seen by the instructions polling it
+                    if self.hold_taps && self.frame_presses & mask != 0 {
+                        self.held_releases |= mask;
+                    } else {
+                        self.key_states &= !mask;
                     }
                 },
-                _ => {},
             }
         }
     }
+    pub fn next_frame(&mut self) {
+        self.key_states &= !self.held_releases;
+        self.held_releases = 0;
+        self.frame_presses = 0;
+    }
+    pub fn clear_key_release(&mut self) {
+        self.last_key_release = None;
+    }
+    pub fn take_key_release(&mut self) -> Option<Key> {
+        self.last_key_release.take()
+    }
     pub fn is_key_pressed(&self, key: Key) -> bool {
         self.key_states & (1 << key as u8) != 0
     }
 }
 
-pub fn input() -> (InputSender, InputReceiver) {
+pub fn input(hold_taps: bool) -> (InputSender, InputReceiver) {
     let key_states = 0;
 
     let (key_tx, key_rx) = mpsc::channel();
-    let (next_key_tx, next_key_rx) = mpsc::sync_channel(0);
 
     let input_tx = InputSender {
         key_tx,
-        next_key_tx,
     };
     let input_rx = InputReceiver {
         key_states,
+        frame_presses: 0,
+        held_releases: 0,
+        hold_taps,
+        last_key_release: None,
+        key_events: VecDeque::new(),
         key_rx,
-        next_key_rx,
     };
 
     (input_tx, input_rx)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    fn tap(hold_taps: bool) -> InputReceiver {
+        let (input_tx, mut input_rx) = input(hold_taps);
+        input_tx.send_key(Key::Key5, KeyState::Pressed).unwrap();
+        input_tx.send_key(Key::Key5, KeyState::Released).unwrap();
+        input_rx.process_key_events(Instant::now());
+        input_rx
+    }
+
+    #[test]
+    fn taps_within_a_frame_are_held_until_it_ends() {
+        let mut input_rx = tap(true);
+        assert!(input_rx.is_key_pressed(Key::Key5));
+        input_rx.next_frame();
+        assert!(!input_rx.is_key_pressed(Key::Key5));
+    }
+
+    #[test]
+    fn taps_are_missed_without_holding() {
+        let input_rx = tap(false);
+        assert!(!input_rx.is_key_pressed(Key::Key5));
+        assert_eq!(input_rx.last_key_release, Some(Key::Key5));
+    }
+
+    #[test]
+    fn held_keys_stay_pressed_across_frames() {
+        let (input_tx, mut input_rx) = input(true);
+        input_tx.send_key(Key::KeyA, KeyState::Pressed).unwrap();
+        input_rx.process_key_events(Instant::now());
+        input_rx.next_frame();
+        assert!(input_rx.is_key_pressed(Key::KeyA));
+        input_tx.send_key(Key::KeyA, KeyState::Released).unwrap();
+        input_rx.process_key_events(Instant::now());
+        // released in a later frame than the press, so nothing holds it
+        assert!(!input_rx.is_key_pressed(Key::KeyA));
+    }
+
+    #[test]
+    fn events_after_the_frame_wait_for_the_next_one() {
+        let (input_tx, mut input_rx) = input(true);
+        let frame_start = Instant::now();
+        input_tx.send_key(Key::Key1, KeyState::Pressed).unwrap();
+        input_rx.process_key_events(frame_start - Duration::from_millis(1));
+        assert!(!input_rx.is_key_pressed(Key::Key1));
+        assert_eq!(input_rx.key_events.len(), 1);
+        input_rx.next_frame();
+        input_rx.process_key_events(Instant::now());
+        assert!(input_rx.is_key_pressed(Key::Key1));
+        assert!(input_rx.key_events.is_empty());
+    }
+}
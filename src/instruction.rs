@@ -0,0 +1,146 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Instruction {
+    Clear,
+    Return,
+    MachineCall(u16),
+    Jump(u16),
+    Call(u16),
+    SkipEqual(u8, u8),
+    SkipNotEqual(u8, u8),
+    SkipRegistersEqual(u8, u8),
+    Set(u8, u8),
+    Add(u8, u8),
+    Move(u8, u8),
+    Or(u8, u8),
+    And(u8, u8),
+    Xor(u8, u8),
+    AddRegisters(u8, u8),
+    Subtract(u8, u8),
+    ShiftRight(u8, u8),
+    SubtractReverse(u8, u8),
+    ShiftLeft(u8, u8),
+    SkipRegistersNotEqual(u8, u8),
+    SetIndex(u16),
+    JumpOffset(u8, u16),
+    Random(u8, u8),
+    Draw(u8, u8, u8),
+    SkipKeyPressed(u8),
+    SkipKeyNotPressed(u8),
+    GetDelay(u8),
+    WaitKey(u8),
+    SetDelay(u8),
+    SetSound(u8),
+    AddIndex(u8),
+    FontCharacter(u8),
+    StoreBcd(u8),
+    StoreRegisters(u8),
+    LoadRegisters(u8),
+    Invalid,
+}
+impl Instruction {
+    pub(crate) fn decode(opcode: u16) -> Self {
+        let x = ((0x0F00 & opcode) >> 8) as u8;
+        let y = ((0x00F0 & opcode) >> 4) as u8;
+        let n = (0x000F & opcode) as u8;
+        let nn = (0x00FF & opcode) as u8;
+        let nnn = 0x0FFF & opcode;
+        match opcode & 0xF000 {
+            0x0000 => match opcode {
+                0x00E0 => Instruction::Clear,
+                0x00EE => Instruction::Return,
+                _ => Instruction::MachineCall(nnn),
+            },
+            0x1000 => Instruction::Jump(nnn),
+            0x2000 => Instruction::Call(nnn),
+            0x3000 => Instruction::SkipEqual(x, nn),
+            0x4000 => Instruction::SkipNotEqual(x, nn),
+            0x5000 => match n {
+                0x0 => Instruction::SkipRegistersEqual(x, y),
+                _ => Instruction::Invalid,
+            },
+            0x6000 => Instruction::Set(x, nn),
+            0x7000 => Instruction::Add(x, nn),
+            0x8000 => match n {
+                0x0 => Instruction::Move(x, y),
+                0x1 => Instruction::Or(x, y),
+                0x2 => Instruction::And(x, y),
+                0x3 => Instruction::Xor(x, y),
+                0x4 => Instruction::AddRegisters(x, y),
+                0x5 => Instruction::Subtract(x, y),
+                0x6 => Instruction::ShiftRight(x, y),
+                0x7 => Instruction::SubtractReverse(x, y),
+                0xE => Instruction::ShiftLeft(x, y),
+                _ => Instruction::Invalid,
+            },
+            0x9000 => match n {
+                0x0 => Instruction::SkipRegistersNotEqual(x, y),
+                _ => Instruction::Invalid,
+            },
+            0xA000 => Instruction::SetIndex(nnn),
+            0xB000 => Instruction::JumpOffset(x, nnn),
+            0xC000 => Instruction::Random(x, nn),
+            0xD000 => Instruction::Draw(x, y, n),
+            0xE000 => match nn {
+                0x9E => Instruction::SkipKeyPressed(x),
+                0xA1 => Instruction::SkipKeyNotPressed(x),
+                _ => Instruction::Invalid,
+            },
+            _ => match nn {
+                0x07 => Instruction::GetDelay(x),
+                0x0A => Instruction::WaitKey(x),
+                0x15 => Instruction::SetDelay(x),
+                0x18 => Instruction::SetSound(x),
+                0x1E => Instruction::AddIndex(x),
+                0x29 => Instruction::FontCharacter(x),
+                0x33 => Instruction::StoreBcd(x),
+                0x55 => Instruction::StoreRegisters(x),
+                0x65 => Instruction::LoadRegisters(x),
+                _ => Instruction::Invalid,
+            },
+        }
+    }
+}
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::Clear => write!(f, "display_clear()"),
+            Instruction::Return => write!(f, "return"),
+            Instruction::MachineCall(nnn) => write!(f, "call {:#05x}", nnn),
+            Instruction::Jump(nnn) => write!(f, "jump {:#05x}", nnn),
+            Instruction::Call(nnn) => write!(f, "*({:#05x})()", nnn),
+            Instruction::SkipEqual(x, nn) => write!(f, "if (V{:01x} == {:#04x})", x, nn),
+            Instruction::SkipNotEqual(x, nn) => write!(f, "if (V{:01x} != {:#04x})", x, nn),
+            Instruction::SkipRegistersEqual(x, y) => write!(f, "if (V{:01x} == V{:01x})", x, y),
+            Instruction::Set(x, nn) => write!(f, "V{:01x} = {:#04x}", x, nn),
+            Instruction::Add(x, nn) => write!(f, "V{:01x} += {:#04x}", x, nn),
+            Instruction::Move(x, y) => write!(f, "V{:01x} = V{:01x}", x, y),
+            Instruction::Or(x, y) => write!(f, "V{:01x} |= V{:01x}", x, y),
+            Instruction::And(x, y) => write!(f, "V{:01x} &= V{:01x}", x, y),
+            Instruction::Xor(x, y) => write!(f, "V{:01x} ^= V{:01x}", x, y),
+            Instruction::AddRegisters(x, y) => write!(f, "V{:01x} += V{:01x}", x, y),
+            Instruction::Subtract(x, y) => write!(f, "V{:01x} -= V{:01x}", x, y),
+            Instruction::ShiftRight(x, _) => write!(f, "V{:01x} >>= 1", x),
+            Instruction::SubtractReverse(x, y) => write!(f, "V{:01x} = V{:01x} - V{:01x}", x, y, x),
+            Instruction::ShiftLeft(x, _) => write!(f, "V{:01x} <<= 1", x),
+            Instruction::SkipRegistersNotEqual(x, y) => write!(f, "if (V{:01x} != V{:01x})", x, y),
+            Instruction::SetIndex(nnn) => write!(f, "I = {:#05x}", nnn),
+            Instruction::JumpOffset(_, nnn) => write!(f, "PC = V0 + {:#05x}", nnn),
+            Instruction::Random(x, nn) => write!(f, "V{:01x} = rand() & {:#04x}", x, nn),
+            Instruction::Draw(x, y, n) => write!(f, "draw(V{:01x}, V{:01x}, {:#04x})", x, y, n),
+            Instruction::SkipKeyPressed(x) => write!(f, "if (key() == V{:01x})", x),
+            Instruction::SkipKeyNotPressed(x) => write!(f, "if (key() != V{:01x})", x),
+            Instruction::GetDelay(x) => write!(f, "V{:01x} = get_delay()", x),
+            Instruction::WaitKey(x) => write!(f, "V{:01x} = get_key()", x),
+            Instruction::SetDelay(x) => write!(f, "delay_timer(V{:01x})", x),
+            Instruction::SetSound(x) => write!(f, "sound_timer(V{:01x})", x),
+            Instruction::AddIndex(x) => write!(f, "I += V{:01x}", x),
+            Instruction::FontCharacter(x) => write!(f, "I = sprite_addr[V{:01x}]", x),
+            Instruction::StoreBcd(x) => write!(f, "set_BCD(V{:01x})", x),
+            Instruction::StoreRegisters(x) => write!(f, "reg_dump(V{:01x}, &I)", x),
+            Instruction::LoadRegisters(x) => write!(f, "reg_load(V{:01x}, &I)", x),
+            Instruction::Invalid => write!(f, "invalid"),
+        }
+    }
+}
@@ -1,35 +1,361 @@
 mod processor;
+mod instruction;
 mod display;
+mod gpu;
+mod software;
+mod crt;
+mod post_process;
+mod framebuffer;
 mod timers;
+mod timing;
 mod input;
+mod rom;
+mod terminal;
+mod bench;
+pub mod config;
+pub mod quirks;
+pub mod palette;
+pub mod persistence;
+pub mod screenshot;
+pub mod recording;
 
-use std::sync::{Mutex, Arc};
+use std::{fmt, io, rc::Rc, time::Instant};
 use display::Display;
-use processor::Processor;
-use winit::{window::WindowBuilder, dpi::{Size, LogicalSize}, event::*, event_loop::{EventLoop, ControlFlow}};
+use recording::Recorder;
+use persistence::Persistence;
+use input::InputSender;
+use processor::{Processor, ProcessorState, FRAME_DURATION};
+use winit::{window::{Window, WindowBuilder, Fullscreen}, dpi::{Size, LogicalSize}, event::*, event_loop::{EventLoop, ControlFlow}};
 
-pub async fn run(program: &[u8]) {
-    env_logger::init();
+pub use config::Config;
+pub use framebuffer::{Framebuffer, DISPLAY_WIDTH, DISPLAY_HEIGHT};
+pub use rom::{Rom, LoadError};
+pub use processor::{Frame, Engine, Fault};
+pub use display::{Backend, PresentMode, DisplayError};
+pub use terminal::{run_terminal, TerminalRendering};
+pub use bench::{run_bench, BenchReport};
+pub use timing::Timing;
+
+#[derive(Debug)]
+pub enum RunError {
+    Load(LoadError),
+    Display(DisplayError),
+    Io(io::Error),
+    Fault(Fault),
+}
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunError::Load(error) => write!(f, "{}", error),
+            RunError::Display(error) => write!(f, "{}", error),
+            RunError::Io(error) => write!(f, "{}", error),
+            RunError::Fault(fault) => write!(f, "{}", fault),
+        }
+    }
+}
+impl std::error::Error for RunError {}
+impl From<LoadError> for RunError {
+    fn from(error: LoadError) -> Self {
+        RunError::Load(error)
+    }
+}
+impl From<DisplayError> for RunError {
+    fn from(error: DisplayError) -> Self {
+        RunError::Display(error)
+    }
+}
+impl From<io::Error> for RunError {
+    fn from(error: io::Error) -> Self {
+        RunError::Io(error)
+    }
+}
+impl From<Fault> for RunError {
+    fn from(fault: Fault) -> Self {
+        RunError::Fault(fault)
+    }
+}
+
+#[derive(Debug)]
+pub enum DifferentialError {
+    Load(LoadError),
+    Diverged { frame: u64, address: u16, opcode: u16, field: &'static str },
+    Fault(Fault),
+}
+impl fmt::Display for DifferentialError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DifferentialError::Load(error) => write!(f, "{}", error),
+            DifferentialError::Diverged { frame, address, opcode, field } => {
+                write!(f, "engines diverged in {} on frame {} after {:04x} at {:#05x}", field, frame, opcode, address)
+            },
+            DifferentialError::Fault(fault) => write!(f, "both engines stopped: {}", fault),
+        }
+    }
+}
+impl std::error::Error for DifferentialError {}
+impl From<LoadError> for DifferentialError {
+    fn from(error: LoadError) -> Self {
+        DifferentialError::Load(error)
+    }
+}
+
+struct Machine {
+    processor: Processor,
+    input_tx: InputSender,
+    frame: Frame,
+    persistence: Persistence,
+    fault: Option<Fault>,
+    crashed: bool,
+}
+impl Machine {
+    fn new(rom: &Rom, config: &Config) -> Result<Self, LoadError> {
+        rom.check(config.start_address)?;
+        let (input_tx, input_rx) = input::input(config.hold_taps);
+        let processor = Processor::new(input_rx, rom.data(), config)?;
+
+        Ok(Self {
+            processor,
+            input_tx,
+            frame: Frame::default(),
+            persistence: Persistence::new(config.persistence, config.persistence_frames),
+            fault: None,
+            crashed: false,
+        })
+    }
+}
+
+#[derive(Default)]
+struct Playback {
+    paused: bool,
+    unfocused: bool,
+    fast_forward: bool,
+    slow_motion: bool,
+}
+
+struct Session {
+    display: Display,
+    window: Rc<Window>,
+    config: Config,
+    rom: Rom,
+    machine: Machine,
+    playback: Playback,
+    recorder: Option<Recorder>,
+    next_frame: Instant,
+}
+impl Session {
+    fn new(display: Display, window: Rc<Window>, config: Config, rom: Rom) -> Result<Self, LoadError> {
+        let machine = Machine::new(&rom, &config)?;
+        let session = Self {
+            display,
+            window,
+            config,
+            rom,
+            machine,
+            playback: Playback::default(),
+            recorder: None,
+            next_frame: Instant::now(),
+        };
+        session.update_title();
+        Ok(session)
+    }
+    fn load(&mut self, rom: Result<Rom, LoadError>) {
+        match rom.and_then(|rom| Ok((Machine::new(&rom, &self.config)?, rom))) {
+            Ok((machine, rom)) => {
+                self.machine = machine;
+                self.rom = rom;
+                self.update_playback();
+                self.present();
+            },
+            Err(error) => log::error!("failed to load rom: {}", error),
+        }
+    }
+    fn update_title(&self) {
+        let mut title = format!("Emu8 - {}", self.rom.name());
+        if self.playback.paused {
+            title.push_str(" (paused)");
+        }
+        if self.recorder.is_some() {
+            title.push_str(" (recording)");
+        }
+        if let Some(fault) = self.machine.fault {
+            title.push_str(&format!(" (halted: {})", fault));
+        } else if self.machine.crashed {
+            title.push_str(" (crashed)");
+        }
+        self.window.set_title(&title);
+    }
+    fn update_playback(&self) {
+        let processor = &self.machine.processor;
+        processor.set_paused(self.playback.paused || self.playback.unfocused);
+        processor.set_speed(if self.playback.fast_forward {
+            self.config.fast_forward
+        } else if self.playback.slow_motion {
+            self.config.slow_motion
+        } else {
+            1.0
+        });
+        self.update_title();
+    }
+    fn handle_key_event(&mut self, key_event: KeyboardInput) {
+        match (key_event.state, key_event.virtual_keycode) {
+            (ElementState::Pressed, Some(VirtualKeyCode::F5)) => {
+                self.load(Ok(self.rom.clone()));
+            },
+            (ElementState::Pressed, Some(VirtualKeyCode::F6)) => {
+                self.load(self.rom.reload());
+            },
+            (ElementState::Pressed, Some(VirtualKeyCode::F7)) => {
+                self.config.palette = self.config.palette.next_theme();
+                self.display.set_palette(self.config.palette);
+                self.window.request_redraw();
+            },
+            (ElementState::Pressed, Some(VirtualKeyCode::F11)) => {
+                let fullscreen = match self.window.fullscreen() {
+                    Some(_) => None,
+                    None => Some(Fullscreen::Borderless(None)),
+                };
+                self.window.set_fullscreen(fullscreen);
+            },
+            (ElementState::Pressed, Some(VirtualKeyCode::F12)) => {
+                self.save_screenshot();
+            },
+            (ElementState::Pressed, Some(VirtualKeyCode::F9)) => {
+                self.toggle_recording();
+            },
+            (ElementState::Pressed, Some(VirtualKeyCode::P)) => {
+                self.playback.paused = !self.playback.paused;
+                self.update_playback();
+            },
+            (ElementState::Pressed, Some(VirtualKeyCode::N)) => {
+                if self.playback.paused {
+                    self.machine.processor.step();
+                }
+            },
+            (state, Some(VirtualKeyCode::Tab)) => {
+                self.playback.fast_forward = state == ElementState::Pressed;
+                self.update_playback();
+            },
+            (ElementState::Pressed, Some(VirtualKeyCode::L)) => {
+                self.playback.slow_motion = !self.playback.slow_motion;
+                self.update_playback();
+            },
+            _ => {
+                let _ = self.machine.input_tx.send_key_event(key_event);
+            },
+        }
+    }
+    fn receive_frames(&mut self) -> bool {
+        let mut changed = false;
+        let mut halted = None;
+        for frame in self.machine.processor.frames() {
+            let mut frame = match frame {
+                Ok(frame) => frame,
+                Err(fault) => {
+                    halted = Some(fault);
+                    continue;
+                },
+            };
+            if let Some(recorder) = &self.recorder {
+                recorder.record_frame(&frame.framebuffer);
+            }
+            // fading follows emulated frames, however many arrive between redraws
+            self.machine.persistence.update(&frame.framebuffer);
+            changed |= self.machine.persistence.changed();
+            changed |= frame.framebuffer != self.machine.frame.framebuffer;
+            frame.framebuffer.merge_dirty(&self.machine.frame.framebuffer);
+            self.machine.frame = frame;
+        }
+        if let Some(fault) = halted {
+            log::error!("{}", fault);
+            self.machine.fault = Some(fault);
+            self.update_title();
+        }
+        if !self.machine.crashed && self.machine.processor.crashed() {
+            log::error!("the emulation thread stopped unexpectedly");
+            self.machine.crashed = true;
+            self.update_title();
+        }
+        changed
+    }
+    fn present(&mut self) {
+        self.display.update(&self.machine.frame.framebuffer, self.machine.persistence.intensities());
+        self.machine.frame.framebuffer.clear_dirty();
+        self.window.request_redraw();
+    }
+    // runs at most once per emulated frame and only redraws when the display would change,
+    // so the event loop sleeps while the game is idle
+    fn tick(&mut self, now: Instant) {
+        if now < self.next_frame {
+            return;
+        }
+        if self.receive_frames() || self.display.animating() {
+            self.present();
+        }
+        self.next_frame = (self.next_frame + FRAME_DURATION).max(now);
+    }
+    fn save_screenshot(&self) {
+        let path = screenshot::timestamped_path(&self.config.capture_directory, &self.rom.name(), "png");
+        match screenshot::save_png(&path, &self.machine.frame.framebuffer, &self.config.palette, self.config.capture_scale) {
+            Ok(()) => log::info!("saved screenshot to {}", path.display()),
+            Err(error) => log::error!("failed to save screenshot to {}: {}", path.display(), error),
+        }
+    }
+    fn toggle_recording(&mut self) {
+        match self.recorder.take() {
+            Some(recorder) => {
+                match recorder.finish() {
+                    Ok(()) => log::info!("stopped recording"),
+                    Err(error) => log::error!("failed to write recording: {}", error),
+                }
+            },
+            None => {
+                let format = self.config.recording_format;
+                let path = screenshot::timestamped_path(&self.config.capture_directory, &self.rom.name(), format.extension());
+                match Recorder::start(&path, format, &self.config.palette, self.config.capture_scale) {
+                    Ok(recorder) => {
+                        log::info!("recording to {}", path.display());
+                        self.recorder = Some(recorder);
+                    },
+                    Err(error) => log::error!("failed to start recording to {}: {}", path.display(), error),
+                }
+            },
+        }
+        self.update_title();
+    }
+    fn set_focused(&mut self, focused: bool) {
+        if self.config.pause_on_focus_loss {
+            self.playback.unfocused = !focused;
+            self.update_playback();
+        }
+    }
+}
+
+pub async fn run(rom: Rom, config: Config) -> Result<(), RunError> {
     let event_loop = EventLoop::new();
-    let window = WindowBuilder::new()
+    let window = Rc::new(WindowBuilder::new()
         .with_title("Emu8")
-        .with_inner_size(Size::Logical(LogicalSize { width: 640.0, height: 320.0 }))
-        .with_resizable(false)
+        .with_inner_size(Size::Logical(LogicalSize {
+            width: (DISPLAY_WIDTH as u32 * config.scale) as f64,
+            height: (DISPLAY_HEIGHT as u32 * config.scale) as f64,
+        }))
+        .with_resizable(true)
+        .with_min_inner_size(Size::Logical(LogicalSize {
+            width: DISPLAY_WIDTH as f64,
+            height: DISPLAY_HEIGHT as f64,
+        }))
+        .with_fullscreen(config.fullscreen.then(|| Fullscreen::Borderless(None)))
         .build(&event_loop)
-        .unwrap();
+        .unwrap());
 
-    let display = Arc::new(Mutex::new(Display::new(&window).await));
+    let display = Display::new(&window, &config).await?;
 
-    let (input_tx, input_rx) = input::input();
-    let input_rx = Arc::new(Mutex::new(input_rx));
-
-    let _processor = Processor::new(input_rx.clone(), display.clone(), program);
+    let mut session = Session::new(display, window, config, rom)?;
 
     event_loop.run(move |event, _, control_flow| match event {
         Event::WindowEvent {
             ref event,
             window_id,
-        } if window_id == window.id() => {
+        } if window_id == session.window.id() => {
             match event {
                 WindowEvent::CloseRequested
                 | WindowEvent::KeyboardInput {
@@ -44,35 +370,160 @@ pub async fn run(program: &[u8]) {
                     *control_flow = ControlFlow::Exit
                 },
                 WindowEvent::Resized(physical_size) => {
-                    display.lock().unwrap().resize(*physical_size);
+                    session.display.resize(*physical_size);
+                    session.window.request_redraw();
                 },
                 WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                    display.lock().unwrap().resize(**new_inner_size);
+                    session.display.resize(**new_inner_size);
+                    session.window.request_redraw();
+                },
+                WindowEvent::DroppedFile(path) => {
+                    session.load(Rom::load(path));
+                },
+                WindowEvent::Focused(focused) => {
+                    session.set_focused(*focused);
                 },
                 WindowEvent::KeyboardInput {
                     input: key_event,
                     ..
                 } => {
-                    input_tx.send_key_event(*key_event).unwrap();
-                    if let Ok(mut input_rx) = input_rx.try_lock() {
-                        input_rx.process_key_events();
-                    }
-                }
+                    session.handle_key_event(*key_event);
+                },
                 _ => {},
             }
         },
-        Event::RedrawRequested(window_id) if window_id == window.id() => {
-            display.lock().unwrap().update();
-            match display.lock().unwrap().render() {
+        Event::RedrawRequested(window_id) if window_id == session.window.id() => {
+            match session.display.render() {
                 Ok(_) => {}
-                Err(wgpu::SurfaceError::Lost) => display.lock().unwrap().resize(display.lock().unwrap().size),
+                Err(wgpu::SurfaceError::Lost) => session.display.resize(session.display.size()),
                 Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
                 Err(err) => eprintln!("{:?}", err),
             }
         },
         Event::MainEventsCleared => {
-            window.request_redraw();
+            session.tick(Instant::now());
+            if *control_flow != ControlFlow::Exit {
+                *control_flow = ControlFlow::WaitUntil(session.next_frame);
+            }
         },
         _ => {},
     });
-}
\ No newline at end of file
+}
+
+pub fn run_headless(rom: &Rom, config: &Config, frames: u64, mut on_frame: impl FnMut(&Frame)) -> Result<Framebuffer, RunError> {
+    rom.check(config.start_address)?;
+    let (_input_tx, input_rx) = input::input(config.hold_taps);
+
+    let mut state = ProcessorState::new(input_rx, rom.data(), config)?;
+    for _ in 0..frames {
+        state.run_frame(Instant::now())?;
+        on_frame(&state.take_frame());
+    }
+    Ok(state.framebuffer().clone())
+}
+
+pub fn run_differential(rom: &Rom, config: &Config, frames: u64) -> Result<Framebuffer, DifferentialError> {
+    rom.check(config.start_address)?;
+    if config.timing != Timing::Instructions {
+        log::warn!("differential runs use instruction timing");
+    }
+    if config.trace.is_some() {
+        log::warn!("differential runs don't write a trace, since traced instructions are never compiled");
+    }
+    let config = Config {
+        seed: Some(config.seed.unwrap_or_else(rand::random)),
+        timing: Timing::Instructions,
+        trace: None,
+        ..config.clone()
+    };
+    let (_input_tx, input_rx) = input::input(config.hold_taps);
+    let mut recompiler = ProcessorState::new(input_rx, rom.data(), &Config {
+        engine: Engine::Recompiler,
+        ..config.clone()
+    }).map_err(LoadError::from)?;
+    let (_reference_input_tx, reference_input_rx) = input::input(config.hold_taps);
+    let mut interpreter = ProcessorState::new(reference_input_rx, rom.data(), &Config {
+        engine: Engine::Interpreter,
+        ..config
+    }).map_err(LoadError::from)?;
+
+    for frame in 0..frames {
+        recompiler.run_frame_against(&mut interpreter, Instant::now())
+            .map_err(|(address, opcode, field)| DifferentialError::Diverged { frame, address, opcode, field })?;
+        if let Some(fault) = recompiler.fault() {
+            return Err(DifferentialError::Fault(fault));
+        }
+    }
+    Ok(recompiler.framebuffer().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+    use super::*;
+    use crate::quirks::QuirkPreset;
+
+    fn rom(name: &str, program: &[u16]) -> Rom {
+        let path = env::temp_dir().join(format!("emu8-{}-{}.ch8", name, std::process::id()));
+        let data: Vec<u8> = program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+        fs::write(&path, data).unwrap();
+        let rom = Rom::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        rom
+    }
+
+    fn assert_engines_agree(rom: &Rom) {
+        for quirks in [QuirkPreset::Chip8, QuirkPreset::Superchip, QuirkPreset::Xochip] {
+            for speed in [7, 100, 1000] {
+                let config = Config {
+                    quirks,
+                    speed,
+                    seed: Some(1),
+                    ..Config::default()
+                };
+                if let Err(error) = run_differential(rom, &config, 120) {
+                    panic!("{:?} at speed {}: {}", quirks, speed, error);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn engines_agree_on_arithmetic_calls_and_draws() {
+        let mut program = vec![
+            0x00E0, 0x6A08, 0x6B04, 0x6C00,
+            // loop: call the arithmetic, then draw a random digit
+            0x2230, 0xC30F, 0xF329, 0xDAB5, 0x7A07, 0x7B03, 0x7C01, 0x4C30, 0x121C, 0x1208,
+            // halt
+            0x121C,
+        ];
+        program.resize(0x18, 0x0000);
+        program.extend([
+            0x8434, 0x8535, 0x8546, 0x864E, 0x8751, 0x8762, 0x8743, 0x8857,
+            // BNNN lands on the return through V0 or V2, which both stay zero
+            0x5450, 0x9460, 0xF41E, 0xF815, 0xF907, 0xB24C, 0x00EE,
+        ]);
+        assert_engines_agree(&rom("arithmetic", &program));
+    }
+
+    #[test]
+    fn engines_agree_on_code_rewritten_by_register_stores() {
+        assert_engines_agree(&rom("store-registers", &[
+            // write 60 V2 over the instruction at 0x20A, which V0 = V2 from the last pass
+            0x6060, 0x8120, 0xA20A, 0xF155, 0x120A,
+            0x0000, 0x8304, 0x7201, 0x3240, 0x1200,
+            0x1214,
+        ]));
+    }
+
+    #[test]
+    fn engines_agree_on_code_rewritten_by_bcd() {
+        assert_engines_agree(&rom("bcd", &[
+            // the hundreds digit of V4 becomes the operand of VA += at 0x208, the tens
+            // and ones turn the next instruction into a machine call that is skipped
+            0x6400, 0xA209, 0xF433, 0x1208,
+            0x7A00, 0x0000, 0x7437, 0x7B01, 0x3B80, 0x1202,
+            0x1214,
+        ]));
+    }
+}
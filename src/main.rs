@@ -1,23 +1,377 @@
-use std::fs::File;
-use std::io::Read;
-use std::io;
-
-fn main() {
-    let mut input = String::new();
-    let mut program = Vec::new();
-    loop {
-        println!("input a path to chip-8 rom");
-        io::stdin().read_line(&mut input).expect("failed to read input");
-        match File::open(&input.trim()) {
-            Ok(mut file) => {
-                file.read_to_end(&mut program).expect("failed to read program from file");
-                pollster::block_on(emu8::run(&program));
-                program.clear();
+use std::{path::PathBuf, process::ExitCode};
+use clap::Parser;
+use emu8::{config, Config, Rom, Backend, PresentMode, TerminalRendering, Timing, Engine, Framebuffer, screenshot, recording::{Recorder, RecordingFormat}, quirks::{QuirkPreset, EdgeMode}, palette::Palette, persistence::PersistenceMode};
+
+const LONG_ABOUT: &str = "chip-8 emulator
+
+The keypad is mapped onto the left of the keyboard:
+  1 2 3 4        1 2 3 C
+  Q W E R   ->   4 5 6 D
+  A S D F        7 8 9 E
+  Z X C V        A 0 B F
+
+Window hotkeys:
+  P      pause or resume
+  N      step one instruction while paused
+  Tab    fast-forward while held (--fast-forward)
+  L      toggle slow motion (slow_motion in the config file)
+  F5     reset the rom
+  F6     reload the rom from disk and reset
+  F7     cycle the color theme
+  F9     start or stop recording (--recording-format)
+  F11    toggle fullscreen
+  F12    save a screenshot
+  Esc    quit";
+
+#[derive(Parser)]
+#[command(version, about = "chip-8 emulator", long_about = LONG_ABOUT)]
+#[command(group(clap::ArgGroup::new("windowless").args(["headless", "bench"]).multiple(true)))]
+struct Args {
+    /// Path to the chip-8 rom to run
+    rom: PathBuf,
+    /// Instructions executed per frame, at 60 frames per second
+    #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..))]
+    speed: Option<u32>,
+    /// Run a fixed number of instructions per frame, or charge each instruction
+    /// its cycle cost on the cosmac vip, which ignores --speed
+    #[arg(long, value_enum)]
+    timing: Option<Timing>,
+    /// Interpret one instruction at a time, or compile straight-line runs of instructions
+    /// into closures, which only applies with instruction timing
+    #[arg(long, value_enum)]
+    engine: Option<Engine>,
+    /// Quirk preset matching the interpreter the rom was written for
+    #[arg(short, long, value_enum)]
+    quirks: Option<QuirkPreset>,
+    /// Whether sprites crossing the left and right edges are clipped or wrap around,
+    /// overriding the quirk preset
+    #[arg(long, value_enum)]
+    horizontal_edge: Option<EdgeMode>,
+    /// Whether sprites crossing the top and bottom edges are clipped or wrap around,
+    /// overriding the quirk preset
+    #[arg(long, value_enum)]
+    vertical_edge: Option<EdgeMode>,
+    /// Whether a sprite draw waits for the next frame like the original interpreter,
+    /// overriding the quirk preset
+    #[arg(long)]
+    display_wait: Option<bool>,
+    /// Color theme (classic, amber, green, lcd, octo) or foreground and background colors,
+    /// e.g. "#ffffff,#000000", optionally followed by the second plane and overlap colors
+    #[arg(short, long)]
+    palette: Option<Palette>,
+    /// Reduce flicker by fading pixels out or blending the last two frames
+    #[arg(long, value_enum)]
+    persistence: Option<PersistenceMode>,
+    /// Number of frames a pixel takes to fade out in fade persistence mode
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    persistence_frames: Option<u32>,
+    /// Apply the crt post-processing pass, configured in the [crt] table of the config file
+    #[arg(long)]
+    crt: bool,
+    /// Wgsl post-processing shader to apply, can be given multiple times to chain shaders
+    #[arg(long = "shader", value_name = "FILE")]
+    shaders: Vec<PathBuf>,
+    /// Window scale in pixels per chip-8 pixel
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    scale: Option<u32>,
+    /// Seed for the random number generator used by CXNN
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Start in borderless fullscreen
+    #[arg(short, long)]
+    fullscreen: bool,
+    /// Only scale the display by whole multiples, leaving a border around it
+    #[arg(long)]
+    integer_scaling: bool,
+    /// Renderer to use, auto falls back to software rendering when no gpu is available
+    #[arg(long, value_enum)]
+    backend: Option<Backend>,
+    /// How frames are presented with the wgpu renderer, fifo waits for vsync while mailbox
+    /// and immediate lower latency, falling back to fifo when unsupported
+    #[arg(long, value_enum)]
+    present_mode: Option<PresentMode>,
+    /// Silence the terminal bell that stands in for sound with --terminal,
+    /// the window has no audio output
+    #[arg(short, long)]
+    mute: bool,
+    /// Speed multiplier while the fast-forward key is held, between 0.01 and 100
+    #[arg(long, value_parser = parse_multiplier)]
+    fast_forward: Option<f64>,
+    /// Pause emulation while the window is not focused
+    #[arg(long)]
+    pause_on_focus_loss: bool,
+    /// Run without a window and print the final display to stdout
+    #[arg(long)]
+    headless: bool,
+    /// Play in the terminal instead of a window, press escape to quit
+    #[arg(long, conflicts_with = "headless")]
+    terminal: bool,
+    /// How the display is drawn with --terminal
+    #[arg(long, value_enum)]
+    terminal_rendering: Option<TerminalRendering>,
+    /// Number of frames to run in headless or bench mode
+    #[arg(long, default_value_t = 600, requires = "windowless", value_parser = clap::value_parser!(u64).range(1..))]
+    frames: u64,
+    /// Number of emulated seconds to run in headless or bench mode, instead of --frames
+    #[arg(long, requires = "windowless", conflicts_with = "frames", value_parser = parse_seconds)]
+    seconds: Option<f64>,
+    /// Run headless on both engines side by side and stop at the first point they disagree
+    #[arg(long, requires = "headless")]
+    differential: bool,
+    /// Run headless as fast as possible and report instructions and frames per second,
+    /// and the time spent on each class of instruction
+    #[arg(long, conflicts_with_all = ["differential", "terminal"])]
+    bench: bool,
+    /// Save the final display as a png in headless mode, named after the rom if no file is given
+    #[arg(long, value_name = "FILE", requires = "headless")]
+    screenshot: Option<Option<PathBuf>>,
+    /// Record every frame in headless mode, named after the rom if no file is given
+    #[arg(long, value_name = "FILE", requires = "headless")]
+    record: Option<Option<PathBuf>>,
+    /// Format of recordings made with --record or the record hotkey
+    #[arg(long, value_enum)]
+    recording_format: Option<RecordingFormat>,
+    /// Write a trace of every executed instruction to this file
+    #[arg(short, long)]
+    trace: Option<PathBuf>,
+    /// Address the rom is loaded at and execution starts from, e.g. 0x600
+    #[arg(long, value_parser = parse_address)]
+    start_address: Option<u16>,
+    /// Read options from this toml file, command-line options take precedence
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+}
+impl Args {
+    fn into_config(self, mut config: Config) -> Config {
+        if let Some(speed) = self.speed {
+            config.speed = speed;
+        }
+        if let Some(timing) = self.timing {
+            config.timing = timing;
+        }
+        if let Some(engine) = self.engine {
+            config.engine = engine;
+        }
+        if let Some(quirks) = self.quirks {
+            config.quirks = quirks;
+        }
+        if self.horizontal_edge.is_some() {
+            config.horizontal_edge = self.horizontal_edge;
+        }
+        if self.vertical_edge.is_some() {
+            config.vertical_edge = self.vertical_edge;
+        }
+        if self.display_wait.is_some() {
+            config.display_wait = self.display_wait;
+        }
+        if let Some(start_address) = self.start_address {
+            config.start_address = start_address;
+        }
+        if let Some(palette) = self.palette {
+            config.palette = palette;
+        }
+        if let Some(persistence) = self.persistence {
+            config.persistence = persistence;
+        }
+        if let Some(persistence_frames) = self.persistence_frames {
+            config.persistence_frames = persistence_frames;
+        }
+        if let Some(scale) = self.scale {
+            config.scale = scale;
+        }
+        if let Some(backend) = self.backend {
+            config.backend = backend;
+        }
+        if let Some(present_mode) = self.present_mode {
+            config.present_mode = present_mode;
+        }
+        if let Some(terminal_rendering) = self.terminal_rendering {
+            config.terminal_rendering = terminal_rendering;
+        }
+        if let Some(fast_forward) = self.fast_forward {
+            config.fast_forward = fast_forward;
+        }
+        if let Some(recording_format) = self.recording_format {
+            config.recording_format = recording_format;
+        }
+        if self.seed.is_some() {
+            config.seed = self.seed;
+        }
+        if self.trace.is_some() {
+            config.trace = self.trace;
+        }
+        config.crt.enabled |= self.crt;
+        config.shaders.extend(self.shaders);
+        config.fullscreen |= self.fullscreen;
+        config.integer_scaling |= self.integer_scaling;
+        config.mute |= self.mute;
+        config.pause_on_focus_loss |= self.pause_on_focus_loss;
+        config
+    }
+}
+
+fn parse_multiplier(string: &str) -> Result<f64, String> {
+    let multiplier: f64 = string.parse().map_err(|error: std::num::ParseFloatError| error.to_string())?;
+    if !config::SPEED_MULTIPLIERS.contains(&multiplier) {
+        return Err(format!("must be between {} and {}", config::SPEED_MULTIPLIERS.start(), config::SPEED_MULTIPLIERS.end()));
+    }
+    Ok(multiplier)
+}
+
+// at least one frame, so rates are never taken over an empty run
+fn parse_seconds(string: &str) -> Result<f64, String> {
+    let seconds: f64 = string.parse().map_err(|error: std::num::ParseFloatError| error.to_string())?;
+    if !seconds.is_finite() {
+        return Err("must be a finite number of seconds".to_string());
+    }
+    if (seconds * 60.0).round() < 1.0 {
+        return Err("must be at least one frame, 1/60 of a second".to_string());
+    }
+    Ok(seconds)
+}
+
+fn parse_address(string: &str) -> Result<u16, String> {
+    let result = match string.strip_prefix("0x").or_else(|| string.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => string.parse(),
+    };
+    result.map_err(|error| error.to_string())
+}
+
+fn main() -> ExitCode {
+    env_logger::init();
+    let args = Args::parse();
+
+    let config = match &args.config {
+        Some(path) => match Config::load(path) {
+            Ok(config) => config,
+            Err(error) => {
+                eprintln!("emu8: failed to load config {}: {}", path.display(), error);
+                return ExitCode::FAILURE;
             },
+        },
+        None => Config::default(),
+    };
+
+    let rom = match Rom::load(&args.rom) {
+        Ok(rom) => rom,
+        Err(error) => {
+            eprintln!("emu8: failed to read rom {}: {}", args.rom.display(), error);
+            return ExitCode::FAILURE;
+        },
+    };
+
+    let headless = args.headless;
+    let differential = args.differential;
+    let terminal = args.terminal;
+    let bench = args.bench;
+    let frames = match args.seconds {
+        Some(seconds) => (seconds * 60.0).round() as u64,
+        None => args.frames,
+    };
+    let screenshot = args.screenshot.clone();
+    let record = args.record.clone();
+    let config = args.into_config(config);
+    if let Err(error) = config.validate() {
+        eprintln!("emu8: invalid configuration: {}", error);
+        return ExitCode::FAILURE;
+    }
+
+    if differential {
+        match emu8::run_differential(&rom, &config, frames) {
+            Ok(framebuffer) => {
+                print_framebuffer(&framebuffer);
+                ExitCode::SUCCESS
+            },
+            Err(error) => {
+                eprintln!("emu8: {}", error);
+                ExitCode::FAILURE
+            },
+        }
+    } else if bench {
+        match emu8::run_bench(&rom, &config, frames) {
+            Ok(report) => {
+                print!("{}", report);
+                ExitCode::SUCCESS
+            },
+            Err(error) => {
+                eprintln!("emu8: {}", error);
+                ExitCode::FAILURE
+            },
+        }
+    } else if headless {
+        run_headless(&rom, &config, frames, screenshot, record)
+    } else if terminal {
+        match emu8::run_terminal(&rom, &config) {
+            Ok(()) => ExitCode::SUCCESS,
             Err(error) => {
-                eprintln!("failed to read error: {}", error);
+                eprintln!("emu8: {}", error);
+                ExitCode::FAILURE
+            },
+        }
+    } else {
+        match pollster::block_on(emu8::run(rom, config)) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(error) => {
+                eprintln!("emu8: {}", error);
+                ExitCode::FAILURE
+            },
+        }
+    }
+}
+
+fn print_framebuffer(framebuffer: &Framebuffer) {
+    for y in 0..framebuffer.height() {
+        let row: String = (0..framebuffer.width())
+            .map(|x| if framebuffer.pixel(x, y) { '#' } else { '.' })
+            .collect();
+        println!("{}", row);
+    }
+}
+
+fn run_headless(rom: &Rom, config: &Config, frames: u64, screenshot: Option<Option<PathBuf>>, record: Option<Option<PathBuf>>) -> ExitCode {
+    let recorder = match record {
+        Some(path) => {
+            let format = config.recording_format;
+            let path = path.unwrap_or_else(|| screenshot::timestamped_path(&config.capture_directory, &rom.name(), format.extension()));
+            match Recorder::start(&path, format, &config.palette, config.capture_scale) {
+                Ok(recorder) => Some(recorder),
+                Err(error) => {
+                    eprintln!("emu8: failed to start recording to {}: {}", path.display(), error);
+                    return ExitCode::FAILURE;
+                },
             }
+        },
+        None => None,
+    };
+
+    let result = emu8::run_headless(rom, config, frames, |frame| {
+        if let Some(recorder) = &recorder {
+            recorder.record_frame(&frame.framebuffer);
         }
-        input.clear();
+    });
+    if let Some(recorder) = recorder {
+        if let Err(error) = recorder.finish() {
+            eprintln!("emu8: failed to write recording: {}", error);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    match result {
+        Ok(framebuffer) => {
+            print_framebuffer(&framebuffer);
+            if let Some(path) = screenshot {
+                let path = path.unwrap_or_else(|| screenshot::timestamped_path(&config.capture_directory, &rom.name(), "png"));
+                if let Err(error) = screenshot::save_png(&path, &framebuffer, &config.palette, config.capture_scale) {
+                    eprintln!("emu8: failed to save screenshot to {}: {}", path.display(), error);
+                    return ExitCode::FAILURE;
+                }
+            }
+            ExitCode::SUCCESS
+        },
+        Err(error) => {
+            eprintln!("emu8: {}", error);
+            ExitCode::FAILURE
+        },
     }
 }
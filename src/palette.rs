@@ -0,0 +1,172 @@
+use std::{fmt, str::FromStr};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+impl Color {
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+    pub fn to_linear(self) -> [f32; 4] {
+        fn channel(value: u8) -> f32 {
+            let value = value as f32 / 255.0;
+            if value <= 0.04045 {
+                value / 12.92
+            } else {
+                ((value + 0.055) / 1.055).powf(2.4)
+            }
+        }
+        [channel(self.r), channel(self.g), channel(self.b), 1.0]
+    }
+}
+impl FromStr for Color {
+    type Err = ParseColorError;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let hex = string.strip_prefix('#').unwrap_or(string);
+        if hex.len() != 6 || !hex.is_ascii() {
+            return Err(ParseColorError(string.to_owned()));
+        }
+        let channel = |range| u8::from_str_radix(&hex[range], 16).map_err(|_| ParseColorError(string.to_owned()));
+        Ok(Self::rgb(channel(0..2)?, channel(2..4)?, channel(4..6)?))
+    }
+}
+impl TryFrom<String> for Color {
+    type Error = ParseColorError;
+
+    fn try_from(string: String) -> Result<Self, Self::Error> {
+        string.parse()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseColorError(String);
+impl fmt::Display for ParseColorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid palette or color `{}`, expected a theme name or hex colors like #ff8800", self.0)
+    }
+}
+impl std::error::Error for ParseColorError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    Classic,
+    Amber,
+    Green,
+    Lcd,
+    Octo,
+}
+impl Theme {
+    pub const ALL: [Theme; 5] = [Theme::Classic, Theme::Amber, Theme::Green, Theme::Lcd, Theme::Octo];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Theme::Classic => "classic",
+            Theme::Amber => "amber",
+            Theme::Green => "green",
+            Theme::Lcd => "lcd",
+            Theme::Octo => "octo",
+        }
+    }
+    pub fn palette(self) -> Palette {
+        let colors = match self {
+            Theme::Classic => [
+                Color::rgb(0x00, 0x00, 0x00),
+                Color::rgb(0xFF, 0xFF, 0xFF),
+                Color::rgb(0xAA, 0xAA, 0xAA),
+                Color::rgb(0x55, 0x55, 0x55),
+            ],
+            Theme::Amber => [
+                Color::rgb(0x1A, 0x0F, 0x00),
+                Color::rgb(0xFF, 0xB0, 0x00),
+                Color::rgb(0xB3, 0x6B, 0x00),
+                Color::rgb(0x66, 0x3C, 0x00),
+            ],
+            Theme::Green => [
+                Color::rgb(0x06, 0x14, 0x06),
+                Color::rgb(0x33, 0xFF, 0x33),
+                Color::rgb(0x1F, 0x9F, 0x1F),
+                Color::rgb(0x0F, 0x4F, 0x0F),
+            ],
+            Theme::Lcd => [
+                Color::rgb(0x9B, 0xBC, 0x0F),
+                Color::rgb(0x0F, 0x38, 0x0F),
+                Color::rgb(0x30, 0x62, 0x30),
+                Color::rgb(0x8B, 0xAC, 0x0F),
+            ],
+            Theme::Octo => [
+                Color::rgb(0x99, 0x66, 0x00),
+                Color::rgb(0xFF, 0xCC, 0x00),
+                Color::rgb(0xFF, 0x66, 0x00),
+                Color::rgb(0x66, 0x22, 0x00),
+            ],
+        };
+        Palette { colors }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Palette {
+    pub colors: [Color; 4],
+}
+impl Palette {
+    pub fn background(&self) -> Color {
+        self.colors[0]
+    }
+    pub fn foreground(&self) -> Color {
+        self.colors[1]
+    }
+    pub fn theme(&self) -> Option<Theme> {
+        Theme::ALL.into_iter().find(|theme| theme.palette() == *self)
+    }
+    pub fn next_theme(&self) -> Palette {
+        let next = match self.theme() {
+            Some(theme) => Theme::ALL[(Theme::ALL.iter().position(|&other| other == theme).unwrap() + 1) % Theme::ALL.len()],
+            None => Theme::ALL[0],
+        };
+        next.palette()
+    }
+}
+impl Default for Palette {
+    fn default() -> Self {
+        Theme::Classic.palette()
+    }
+}
+impl FromStr for Palette {
+    type Err = ParseColorError;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        if let Some(theme) = Theme::ALL.into_iter().find(|theme| theme.name() == string) {
+            return Ok(theme.palette());
+        }
+        let colors = string
+            .split(',')
+            .map(|color| color.trim().parse())
+            .collect::<Result<Vec<Color>, _>>()?;
+        match colors[..] {
+            [foreground, background] => {
+                let mut palette = Palette::default();
+                palette.colors[0] = background;
+                palette.colors[1] = foreground;
+                Ok(palette)
+            },
+            [foreground, background, plane_2, overlap] => Ok(Palette {
+                colors: [background, foreground, plane_2, overlap],
+            }),
+            _ => Err(ParseColorError(string.to_owned())),
+        }
+    }
+}
+impl TryFrom<String> for Palette {
+    type Error = ParseColorError;
+
+    fn try_from(string: String) -> Result<Self, Self::Error> {
+        string.parse()
+    }
+}
@@ -0,0 +1,62 @@
+use serde::Deserialize;
+use crate::framebuffer::Framebuffer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PersistenceMode {
+    #[default]
+    Off,
+    Fade,
+    Blend,
+}
+
+pub struct Persistence {
+    mode: PersistenceMode,
+    decay: u8,
+    intensities: Vec<u8>,
+    previous: Framebuffer,
+    changed: bool,
+}
+impl Persistence {
+    pub fn new(mode: PersistenceMode, frames: u32) -> Self {
+        Self {
+            mode,
+            decay: (u8::MAX as u32).div_ceil(frames.max(1)) as u8,
+            intensities: Vec::new(),
+            previous: Framebuffer::new(),
+            changed: false,
+        }
+    }
+    pub fn intensities(&self) -> &[u8] {
+        &self.intensities
+    }
+    pub fn changed(&self) -> bool {
+        self.changed
+    }
+    pub fn update(&mut self, framebuffer: &Framebuffer) {
+        let (width, height) = (framebuffer.width(), framebuffer.height());
+        if self.intensities.len() != width * height {
+            self.intensities = vec![0; width * height];
+            self.previous = Framebuffer::with_size(width, height, framebuffer.planes());
+        }
+        self.changed = false;
+        for y in 0..height {
+            for x in 0..width {
+                let intensity = &mut self.intensities[y * width + x];
+                let pixel = framebuffer.pixel(x, y);
+                let previous = *intensity;
+                *intensity = match self.mode {
+                    PersistenceMode::Off => 0,
+                    PersistenceMode::Fade if pixel => u8::MAX,
+                    PersistenceMode::Fade => intensity.saturating_sub(self.decay),
+                    PersistenceMode::Blend if self.previous.pixel(x, y) => u8::MAX,
+                    PersistenceMode::Blend => 0,
+                };
+                self.changed |= *intensity != previous;
+            }
+        }
+        if self.mode == PersistenceMode::Blend {
+            self.previous = framebuffer.clone();
+        }
+    }
+}
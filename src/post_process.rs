@@ -0,0 +1,333 @@
+use std::{fs, path::{Path, PathBuf}, time::{Instant, SystemTime, Duration}};
+use wgpu::*;
+use crate::crt::{self, CrtConfig};
+
+const PRELUDE: &str = include_str!("post_process.wgsl");
+const RELOAD_INTERVAL: Duration = Duration::from_millis(500);
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct Globals {
+    resolution: [f32; 2],
+    source_size: [f32; 2],
+    time: f32,
+    frame: u32,
+    _padding: [u32; 2],
+    palette: [[f32; 4]; 4],
+}
+
+struct Pass {
+    render_pipeline: RenderPipeline,
+    uniform_buffer: Buffer,
+}
+impl Pass {
+    fn new(device: &Device, layout: &PipelineLayout, format: TextureFormat, label: &str, source: &str, uniform: &[u8]) -> Self {
+        use util::DeviceExt;
+
+        let shader = device.create_shader_module(&ShaderModuleDescriptor {
+            label: Some(label),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+        let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[ColorTargetState {
+                    format,
+                    blend: Some(BlendState::REPLACE),
+                    write_mask: ColorWrites::ALL,
+                }],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            multiview: None,
+        });
+        let uniform_buffer = device.create_buffer_init(
+            &util::BufferInitDescriptor {
+                label: Some(label),
+                contents: uniform,
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            }
+        );
+
+        Self {
+            render_pipeline,
+            uniform_buffer,
+        }
+    }
+}
+
+struct UserPass {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    pass: Option<Pass>,
+}
+
+pub struct PostProcess {
+    crt_config: CrtConfig,
+    integer_scaling: bool,
+    crt: Option<Pass>,
+    user_passes: Vec<UserPass>,
+    globals: Globals,
+    start: Instant,
+    last_reload_check: Instant,
+    format: TextureFormat,
+    sampler: Sampler,
+    bind_group_layout: BindGroupLayout,
+    pipeline_layout: PipelineLayout,
+    targets: [TextureView; 2],
+}
+impl PostProcess {
+    pub fn new(device: &Device, surface_config: &SurfaceConfiguration, source_size: [f32; 2], integer_scaling: bool, crt_config: CrtConfig, shaders: &[PathBuf]) -> Self {
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("post_process_sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: TextureViewDimension::D2,
+                        sample_type: TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("post_process_bind_group_layout"),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("post_process_pipeline_layout"),
+            bind_group_layouts: &[
+                &bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let crt = crt_config.enabled.then(|| Pass::new(
+            device,
+            &pipeline_layout,
+            surface_config.format,
+            "crt",
+            include_str!("crt.wgsl"),
+            bytemuck::bytes_of(&crt::uniform(surface_config, source_size, integer_scaling, &crt_config)),
+        ));
+
+        let globals = Globals {
+            resolution: [surface_config.width as f32, surface_config.height as f32],
+            source_size,
+            time: 0.0,
+            frame: 0,
+            _padding: [0; 2],
+            palette: [[0.0; 4]; 4],
+        };
+
+        let mut post_process = Self {
+            crt_config,
+            integer_scaling,
+            crt,
+            user_passes: Vec::new(),
+            globals,
+            start: Instant::now(),
+            last_reload_check: Instant::now(),
+            format: surface_config.format,
+            sampler,
+            bind_group_layout,
+            pipeline_layout,
+            targets: [
+                Self::create_target(device, surface_config),
+                Self::create_target(device, surface_config),
+            ],
+        };
+        for path in shaders {
+            let pass = Self::load_user_pass(device, &post_process.pipeline_layout, post_process.format, path, &post_process.globals);
+            post_process.user_passes.push(UserPass {
+                path: path.clone(),
+                modified: Self::modified(path),
+                pass,
+            });
+        }
+        post_process
+    }
+    fn create_target(device: &Device, surface_config: &SurfaceConfiguration) -> TextureView {
+        device.create_texture(&TextureDescriptor {
+            label: Some("post_process_target"),
+            size: Extent3d {
+                width: surface_config.width,
+                height: surface_config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: surface_config.format,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        }).create_view(&TextureViewDescriptor::default())
+    }
+    fn load_user_pass(device: &Device, pipeline_layout: &PipelineLayout, format: TextureFormat, path: &Path, globals: &Globals) -> Option<Pass> {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(error) => {
+                log::error!("failed to read shader {}: {}", path.display(), error);
+                return None;
+            },
+        };
+
+        device.push_error_scope(ErrorFilter::Validation);
+        let pass = Pass::new(
+            device,
+            pipeline_layout,
+            format,
+            &path.to_string_lossy(),
+            &format!("{}\n{}", PRELUDE, source),
+            bytemuck::bytes_of(globals),
+        );
+        match pollster::block_on(device.pop_error_scope()) {
+            Some(error) => {
+                log::error!("failed to compile shader {}: {}", path.display(), error);
+                None
+            },
+            None => {
+                log::info!("loaded shader {}", path.display());
+                Some(pass)
+            },
+        }
+    }
+    fn modified(path: &Path) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+    }
+    fn reload(&mut self, device: &Device) {
+        for user_pass in &mut self.user_passes {
+            let modified = Self::modified(&user_pass.path);
+            if modified == user_pass.modified {
+                continue;
+            }
+            user_pass.modified = modified;
+            if let Some(pass) = Self::load_user_pass(device, &self.pipeline_layout, self.format, &user_pass.path, &self.globals) {
+                user_pass.pass = Some(pass);
+                self.start = Instant::now();
+                self.globals.frame = 0;
+            }
+        }
+    }
+    fn passes(&self) -> impl Iterator<Item = &Pass> {
+        self.crt.iter().chain(self.user_passes.iter().filter_map(|user_pass| user_pass.pass.as_ref()))
+    }
+    pub fn enabled(&self) -> bool {
+        self.passes().next().is_some()
+    }
+    // reloads are checked while rendering, so a shader that failed to compile keeps the
+    // display redrawing until it is fixed
+    pub fn animated(&self) -> bool {
+        !self.user_passes.is_empty()
+    }
+    pub fn target_view(&self) -> &TextureView {
+        &self.targets[0]
+    }
+    pub fn resize(&mut self, device: &Device, queue: &Queue, surface_config: &SurfaceConfiguration) {
+        self.targets = [
+            Self::create_target(device, surface_config),
+            Self::create_target(device, surface_config),
+        ];
+        self.globals.resolution = [surface_config.width as f32, surface_config.height as f32];
+        self.write_crt_uniform(queue, surface_config);
+    }
+    pub fn set_source_size(&mut self, queue: &Queue, surface_config: &SurfaceConfiguration, source_size: [f32; 2]) {
+        self.globals.source_size = source_size;
+        self.write_crt_uniform(queue, surface_config);
+    }
+    fn write_crt_uniform(&self, queue: &Queue, surface_config: &SurfaceConfiguration) {
+        if let Some(crt) = &self.crt {
+            queue.write_buffer(&crt.uniform_buffer, 0, bytemuck::bytes_of(&crt::uniform(surface_config, self.globals.source_size, self.integer_scaling, &self.crt_config)));
+        }
+    }
+    pub fn render(&mut self, device: &Device, queue: &Queue, encoder: &mut CommandEncoder, output_view: &TextureView, palette: [[f32; 4]; 4]) {
+        if self.last_reload_check.elapsed() >= RELOAD_INTERVAL {
+            self.last_reload_check = Instant::now();
+            self.reload(device);
+        }
+
+        self.globals.time = self.start.elapsed().as_secs_f32();
+        self.globals.palette = palette;
+        for user_pass in self.user_passes.iter().filter_map(|user_pass| user_pass.pass.as_ref()) {
+            queue.write_buffer(&user_pass.uniform_buffer, 0, bytemuck::bytes_of(&self.globals));
+        }
+        self.globals.frame = self.globals.frame.wrapping_add(1);
+
+        let pass_count = self.passes().count();
+        for (index, pass) in self.passes().enumerate() {
+            let source_view = &self.targets[index % 2];
+            let target_view = if index + 1 == pass_count {
+                output_view
+            } else {
+                &self.targets[(index + 1) % 2]
+            };
+            let bind_group = device.create_bind_group(&BindGroupDescriptor {
+                layout: &self.bind_group_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(source_view),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::Sampler(&self.sampler),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: pass.uniform_buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("post_process_bind_group"),
+            });
+
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("post_process_render_pass"),
+                color_attachments: &[RenderPassColorAttachment {
+                    view: target_view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(Color::BLACK),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+
+            render_pass.set_pipeline(&pass.render_pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
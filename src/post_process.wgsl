@@ -0,0 +1,49 @@
+// Prelude prepended to every user post-processing shader.
+//
+// A user shader only has to define the fragment entry point
+//
+//     [[stage(fragment)]]
+//     fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> { ... }
+//
+// which is run once per pixel of the window. `in.uv` goes from (0, 0) at the
+// top left to (1, 1) at the bottom right. `source` holds the output of the
+// previous pass, which is the emulated display for the first shader.
+//
+// `globals` is updated every frame:
+//   resolution   size of the window in pixels
+//   source_size  size of the emulated display in chip-8 pixels
+//   time         seconds since the shader was loaded
+//   frame        number of frames rendered since the shader was loaded
+//   palette      the active palette in linear color: background, foreground,
+//                second plane and plane overlap
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+};
+
+struct Globals {
+    resolution: vec2<f32>;
+    source_size: vec2<f32>;
+    time: f32;
+    frame: u32;
+    palette: array<vec4<f32>, 4>;
+};
+
+[[group(0), binding(0)]]
+var source: texture_2d<f32>;
+[[group(0), binding(1)]]
+var source_sampler: sampler;
+[[group(0), binding(2)]]
+var<uniform> globals: Globals;
+
+[[stage(vertex)]]
+fn vs_main(
+    [[builtin(vertex_index)]] vertex_index: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    out.uv = uv;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    return out;
+}
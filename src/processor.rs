@@ -1,13 +1,17 @@
-use std::{sync::{Arc, Mutex}, ops::Range, io::{Read, Cursor}};
-use crate::{timers::Timers, input::InputReceiver, display::Display};
-use rand::Rng;
-use timer::{Timer, Guard};
-use chrono::Duration;
-use byteorder::{ReadBytesExt, BigEndian};
-
-const MEMORY_SIZE: usize = 4096;
-const FONT_RANGE: Range<usize> = 0x50..0x9F;
-const FONT: [u8; FONT_RANGE.end - FONT_RANGE.start + 1] = [
+use std::{fmt, sync::mpsc::{self, Sender, Receiver, TryIter, RecvTimeoutError}, ops::Range, time::{Duration, Instant}, fs::File, io::{self, Write, BufWriter}, thread::{self, JoinHandle}};
+use crate::{timing::{self, Timing, VIP_CYCLES_PER_FRAME, VIP_DISPLAY_CYCLES}, timers::Timers, input::{InputReceiver, Key}, instruction::Instruction, framebuffer::Framebuffer, quirks::Quirks, config::Config, bench::Profile};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use recompiler::Block;
+
+mod recompiler;
+
+pub use recompiler::Engine;
+
+pub(crate) const MEMORY_SIZE: usize = 4096;
+pub(crate) const FONT_RANGE: Range<usize> = 0x50..0xA0;
+// the most subroutine calls modern interpreters nest
+const STACK_DEPTH: usize = 16;
+const FONT: [u8; FONT_RANGE.end - FONT_RANGE.start] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
     0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
@@ -25,318 +29,1066 @@ const FONT: [u8; FONT_RANGE.end - FONT_RANGE.start + 1] = [
     0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
-const PROGRAM_START: u16 = 0x200;
+pub const FRAME_DURATION: Duration = Duration::from_nanos(16666667);
+
+// the machine halts on the instruction that caused it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    InvalidOpcode { address: u16, opcode: u16 },
+    StackUnderflow { address: u16 },
+    StackOverflow { address: u16 },
+    // a jump or skip left the program counter where no whole instruction fits
+    OutOfMemory { address: u16 },
+}
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::InvalidOpcode { address, opcode } => write!(f, "invalid opcode {:04x} at {:#05x}", opcode, address),
+            Fault::StackUnderflow { address } => write!(f, "return outside of a subroutine at {:#05x}", address),
+            Fault::StackOverflow { address } => write!(f, "more than {} nested subroutine calls at {:#05x}", STACK_DEPTH, address),
+            Fault::OutOfMemory { address } => write!(f, "program counter {:#05x} is outside memory", address),
+        }
+    }
+}
+impl std::error::Error for Fault {}
 
-struct ProcessorState {
+pub(crate) fn is_valid_opcode(opcode: u16) -> bool {
+    !matches!(Instruction::decode(opcode), Instruction::Invalid | Instruction::MachineCall(_))
+}
+
+pub(crate) struct ProcessorState {
     memory: [u8; MEMORY_SIZE],
+    decoded: [Option<Instruction>; MEMORY_SIZE],
+    engine: Engine,
+    blocks: Vec<Option<Box<Block>>>,
+    code_writes: u64,
+    instructions: u64,
     program_counter: u16,
     registers: [u8; 16],
     index_register: u16,
     stack: Vec<u16>,
     timers: Timers,
-    input: Arc<Mutex<InputReceiver>>,
-    display: Arc<Mutex<Display>>,
+    input: InputReceiver,
+    waiting_for_key: bool,
+    framebuffer: Framebuffer,
+    speed: u32,
+    timing: Timing,
+    cycles: i64,
+    quirks: Quirks,
+    rng: StdRng,
+    trace: Option<BufWriter<File>>,
+    fault: Option<Fault>,
 }
 impl ProcessorState {
-    fn new(input: Arc<Mutex<InputReceiver>>, display: Arc<Mutex<Display>>, program: &[u8]) -> Self {
+    pub(crate) fn new(input: InputReceiver, program: &[u8], config: &Config) -> io::Result<Self> {
         let mut memory = [0; MEMORY_SIZE];
-        for (memory_byte, font_byte) in memory[FONT_RANGE].iter_mut().zip(FONT.iter()) {
-            *memory_byte = *font_byte;
-        }
+        memory[FONT_RANGE].copy_from_slice(&FONT);
 
-        Cursor::new(program).read(&mut memory[PROGRAM_START as usize..]).unwrap();
+        let program_size = program.len().min(MEMORY_SIZE - config.start_address as usize);
+        memory[config.start_address as usize..][..program_size].copy_from_slice(&program[..program_size]);
 
-        let program_counter = PROGRAM_START;
+        let program_counter = config.start_address;
      
         let registers = [0; 16];
         let index_register = 0;
 
-        let stack = Vec::new();
+        let stack = Vec::with_capacity(STACK_DEPTH);
 
         let timers = Timers::new();
 
-        Self {
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        let trace = match &config.trace {
+            Some(path) => Some(BufWriter::new(File::create(path)?)),
+            None => None,
+        };
+
+        Ok(Self {
             memory,
+            decoded: [None; MEMORY_SIZE],
+            engine: config.engine,
+            blocks: (0..MEMORY_SIZE).map(|_| None).collect(),
+            code_writes: 0,
+            instructions: 0,
             program_counter,
             registers,
             index_register,
             stack,
             timers,
             input,
-            display,
-        }
+            waiting_for_key: false,
+            framebuffer: Framebuffer::new(),
+            speed: config.speed,
+            timing: config.timing,
+            cycles: 0,
+            quirks: config.quirks(),
+            rng,
+            trace,
+            fault: None,
+        })
     }
-    fn process(&mut self) {
-        if let Ok(opcode) = (&self.memory[self.program_counter as usize..]).read_u16::<BigEndian>() {
-            self.program_counter += 2;
-            match opcode & 0xF000 {
-                0x0000 => match opcode {
-                    0x00E0 => {
-                        println!("display_clear()");
-                        let mut display = self.display.lock().unwrap();
-                        display.clear();
-                    },
-                    0x00EE => {
-                        println!("return");
-                        self.program_counter = self.stack.pop().expect("can't return outside of subroutine");
-                    },
-                    _ => {
-                        println!("call {:#05x}", 0x0FFF & opcode);
-                        unimplemented!();
-                    },
-                }
-                0x1000 => {
-                    println!("jump {:#05x}", 0x0FFF & opcode);
-                    let nnn = 0x0FFF & opcode;
-                    self.program_counter = nnn;
-                },
-                0x2000 => {
-                    println!("*({:#05x})()", 0x0FFF & opcode);
-                    let nnn = 0x0FFF & opcode;
-                    self.stack.push(self.program_counter);
-                    self.program_counter = nnn;
-                },
-                0x3000 => {
-                    println!("if (V{:01x} == {:#04x})", (0x0F00 & opcode) >> 8, 0x00FF & opcode);
-                    let x = ((0x0F00 & opcode) >> 8) as usize;
-                    let nn = (0x00FF & opcode) as u8;
-                    if self.registers[x] == nn {
-                        self.program_counter += 2;
+    pub(crate) fn run_frame(&mut self, now: Instant) -> Result<(), Fault> {
+        self.run_frame_with(now, Self::process)
+    }
+    // times every instruction, so this runs on the interpreter whatever the engine
+    pub(crate) fn run_frame_profiled(&mut self, now: Instant, profile: &mut Profile) -> Result<(), Fault> {
+        let engine = std::mem::replace(&mut self.engine, Engine::Interpreter);
+        let result = self.run_frame_with(now, |state| {
+            let start = Instant::now();
+            let instruction = state.process();
+            if let Some(instruction) = instruction {
+                profile.record(instruction, start.elapsed());
+            }
+            instruction
+        });
+        self.engine = engine;
+        result
+    }
+    #[inline(always)]
+    fn run_frame_with(&mut self, now: Instant, mut step: impl FnMut(&mut Self) -> Option<Instruction>) -> Result<(), Fault> {
+        if let Some(fault) = self.fault {
+            return Err(fault);
+        }
+        self.input.process_key_events(now);
+        match self.timing {
+            Timing::Instructions if self.engine == Engine::Recompiler => {
+                let mut remaining = self.speed;
+                while let Some((executed, drew)) = self.run_block(remaining) {
+                    remaining -= executed;
+                    if remaining == 0 || self.fault.is_some() || (self.quirks.display_wait && drew) {
+                        break;
                     }
-                },
-                0x4000 => {
-                    println!("if (V{:01x} != {:#04x})", (0x0F00 & opcode) >> 8, 0x00FF & opcode);
-                    let x = ((0x0F00 & opcode) >> 8) as usize;
-                    let nn = (0x00FF & opcode) as u8;
-                    if self.registers[x] != nn {
-                        self.program_counter += 2;
+                }
+            },
+            Timing::Instructions => {
+                for _ in 0..self.speed {
+                    let instruction = step(self);
+                    if self.fault.is_some() || (self.quirks.display_wait && matches!(instruction, Some(Instruction::Draw(..)))) {
+                        break;
                     }
+                }
+            },
+            Timing::Vip => self.run_vip_cycles(&mut step),
+        }
+        self.timers.tick();
+        self.input.next_frame();
+        match self.fault {
+            Some(fault) => Err(fault),
+            None => Ok(()),
+        }
+    }
+    // runs a frame with the recompiler, stepping the interpreting reference along with
+    // every compiled instruction, and stops at the first instruction after which they
+    // disagree with its address, opcode and the part of the machine that differs
+    pub(crate) fn run_frame_against(&mut self, reference: &mut ProcessorState, now: Instant) -> Result<(), (u16, u16, &'static str)> {
+        self.input.process_key_events(now);
+        reference.input.process_key_events(now);
+        let mut remaining = self.speed;
+        let mut divergence = None;
+        while remaining > 0 {
+            let Some((executed, drew)) = self.run_block_with(remaining, |state, address| {
+                let opcode = reference.opcode(address as usize).unwrap_or_default();
+                reference.process();
+                divergence = state.divergence(reference).map(|field| (address, opcode, field));
+                divergence.is_none()
+            }) else {
+                break;
+            };
+            if let Some(divergence) = divergence {
+                return Err(divergence);
+            }
+            remaining -= executed;
+            if self.fault.is_some() || (self.quirks.display_wait && drew) {
+                break;
+            }
+        }
+        for state in [&mut *self, reference] {
+            state.timers.tick();
+            state.input.next_frame();
+        }
+        Ok(())
+    }
+    fn divergence(&self, other: &ProcessorState) -> Option<&'static str> {
+        if self.program_counter != other.program_counter {
+            Some("program counter")
+        } else if self.registers != other.registers {
+            Some("registers")
+        } else if self.index_register != other.index_register {
+            Some("index register")
+        } else if self.stack != other.stack {
+            Some("stack")
+        } else if (self.timers.delay_timer, self.timers.sound_timer) != (other.timers.delay_timer, other.timers.sound_timer) {
+            Some("timers")
+        } else if self.waiting_for_key != other.waiting_for_key {
+            Some("key wait")
+        } else if self.memory != other.memory {
+            Some("memory")
+        } else if self.framebuffer != other.framebuffer {
+            Some("display")
+        } else if self.fault != other.fault {
+            Some("fault")
+        } else {
+            None
+        }
+    }
+    pub(crate) fn fault(&self) -> Option<Fault> {
+        self.fault
+    }
+    pub(crate) fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+    pub(crate) fn take_frame(&mut self) -> Frame {
+        let frame = Frame {
+            framebuffer: self.framebuffer.clone(),
+            sound: self.sound_playing(),
+        };
+        self.framebuffer.clear_dirty();
+        frame
+    }
+    fn run_vip_cycles(&mut self, step: &mut impl FnMut(&mut Self) -> Option<Instruction>) {
+        self.cycles += VIP_CYCLES_PER_FRAME - VIP_DISPLAY_CYCLES;
+        while self.cycles > 0 {
+            let Some(instruction) = self.fetch() else {
+                break;
+            };
+            self.cycles -= timing::vip_cycles(instruction, &self.registers) as i64;
+            step(self);
+            if self.fault.is_some() {
+                break;
+            }
+            // a draw waits for the display interrupt, which forfeits the rest of the frame
+            if let Instruction::Draw(..) = instruction {
+                self.cycles = self.cycles.min(0);
+                break;
+            }
+        }
+    }
+    pub(crate) fn program_counter(&self) -> u16 {
+        self.program_counter
+    }
+    pub(crate) fn index_register(&self) -> u16 {
+        self.index_register
+    }
+    pub(crate) fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+    pub(crate) fn instructions(&self) -> u64 {
+        self.instructions
+    }
+    pub(crate) fn speed(&self) -> u32 {
+        self.speed
+    }
+    pub(crate) fn timing(&self) -> Timing {
+        self.timing
+    }
+    pub(crate) fn sound_playing(&self) -> bool {
+        self.timers.sound_timer > 0
+    }
+    #[cold]
+    fn trace(&mut self, instruction: Instruction) {
+        let address = self.program_counter.wrapping_sub(2);
+        let opcode = self.opcode(address as usize).unwrap_or_default();
+        if let Some(trace) = &mut self.trace {
+            if let Err(error) = writeln!(trace, "{:#05x}  {:04x}  {}", address, opcode, instruction) {
+                log::error!("failed to write trace: {}", error);
+                self.trace = None;
+            }
+        }
+    }
+    fn opcode(&self, address: usize) -> Option<u16> {
+        Some(u16::from_be_bytes([*self.memory.get(address)?, *self.memory.get(address + 1)?]))
+    }
+    // decodes on first execution, entries are dropped when the memory under them is written
+    #[inline(always)]
+    fn decode(&mut self, address: usize) -> Option<Instruction> {
+        match self.decoded.get(address) {
+            Some(Some(instruction)) => Some(*instruction),
+            _ => {
+                let instruction = Instruction::decode(self.opcode(address)?);
+                self.decoded[address] = Some(instruction);
+                Some(instruction)
+            },
+        }
+    }
+    fn write_memory(&mut self, address: usize, value: u8) {
+        let address = address % MEMORY_SIZE;
+        self.memory[address] = value;
+        for address in address.saturating_sub(1)..=address {
+            // compiled blocks only cover decoded instructions, so writes elsewhere keep them
+            if self.decoded[address].take().is_some() {
+                self.code_writes += 1;
+                recompiler::invalidate(self, address);
+            }
+        }
+    }
+    fn fetch(&mut self) -> Option<Instruction> {
+        let instruction = self.decode(self.program_counter as usize);
+        if instruction.is_none() {
+            self.fault = Some(Fault::OutOfMemory { address: self.program_counter });
+        }
+        instruction
+    }
+    fn process(&mut self) -> Option<Instruction> {
+        let instruction = self.fetch()?;
+        self.instructions += 1;
+        self.execute(instruction);
+        Some(instruction)
+    }
+    // runs up to limit instructions of the block at the program counter and returns
+    // how many ran and whether the block's closing draw was reached
+    fn run_block(&mut self, limit: u32) -> Option<(u32, bool)> {
+        self.run_block_with(limit, |_, _| true)
+    }
+    // check gets the address of every instruction after it ran, and ends the block early
+    // by returning false
+    #[inline(always)]
+    fn run_block_with(&mut self, limit: u32, mut check: impl FnMut(&mut Self, u16) -> bool) -> Option<(u32, bool)> {
+        let address = self.program_counter as usize;
+        let block = match self.blocks.get_mut(address).and_then(Option::take) {
+            Some(block) => block,
+            None => match recompiler::compile(self, address) {
+                Some(block) => Box::new(block),
+                None => {
+                    self.fault = Some(Fault::OutOfMemory { address: self.program_counter });
+                    return None;
                 },
-                0x5000 => match opcode & 0x000F { 
-                    0x0000 => {
-                        println!("if (V{:01x} == V{:01x})", (0x0F00 & opcode) >> 8,  (0x00F0 & opcode) >> 4);
-                        let x = ((0x0F00 & opcode) >> 8) as usize;
-                        let y = ((0x00F0 & opcode) >> 4) as usize;
-                        if self.registers[x] == self.registers[y] {
-                            self.program_counter += 2;
-                        }
-                    },
-                    _ => panic!("unknown opcode {:#06x}", opcode),
-                },
-                0x6000 => {
-                    println!("V{:01x} = {:#04x}", (0x0F00 & opcode) >> 8, 0x00FF & opcode);
-                    let x = ((0x0F00 & opcode) >> 8) as usize;
-                    let nn = (0x00FF & opcode) as u8;
-                    self.registers[x] = nn;
-                },
-                0x7000 => {
-                    println!("V{:01x} += {:#04x}", (0x0F00 & opcode) >> 8, 0x00FF & opcode);
-                    let x = ((0x0F00 & opcode) >> 8) as usize;
-                    let nn = (0x00FF & opcode) as u8;
-                    self.registers[x] = self.registers[x].wrapping_add(nn);
+            },
+        };
+        let code_writes = self.code_writes;
+        let mut executed = 0;
+        for op in &block.ops[..block.ops.len().min(limit as usize)] {
+            let instruction_address = self.program_counter;
+            op(self);
+            executed += 1;
+            if !check(self, instruction_address) {
+                break;
+            }
+        }
+        let drew = block.ends_with_draw && executed == block.ops.len();
+        self.instructions += executed as u64;
+        // an idle block would only spin until the frame ends, so it uses up the rest of the
+        // frame without counting the jumps it skipped
+        let executed = if block.idle { limit } else { executed as u32 };
+        // the block is out of the cache while it runs, so check it didn't rewrite itself
+        if self.code_writes == code_writes || recompiler::intact(self, address, &block) {
+            self.blocks[address] = Some(block);
+        }
+        Some((executed, drew))
+    }
+    #[inline(always)]
+    fn execute(&mut self, instruction: Instruction) {
+        self.program_counter += 2;
+        if self.trace.is_some() {
+            self.trace(instruction);
+        }
+        match instruction {
+            Instruction::Clear => self.framebuffer.clear(),
+            Instruction::Return => match self.stack.pop() {
+                Some(address) => self.program_counter = address,
+                None => {
+                    self.program_counter -= 2;
+                    self.fault = Some(Fault::StackUnderflow { address: self.program_counter });
                 },
-                0x8000 => match opcode & 0x000F { 
-                    0x0000 => {
-                        println!("V{:01x} = V{:01x}", (0x0F00 & opcode) >> 8,  (0x00F0 & opcode) >> 4);
-                        let x = ((0x0F00 & opcode) >> 8) as usize;
-                        let y = ((0x00F0 & opcode) >> 4) as usize;
-                        self.registers[x] = self.registers[y];
-                    },
-                    0x0001 => {
-                        println!("V{:01x} |= V{:01x}", (0x0F00 & opcode) >> 8,  (0x00F0 & opcode) >> 4);
-                        let x = ((0x0F00 & opcode) >> 8) as usize;
-                        let y = ((0x00F0 & opcode) >> 4) as usize;
-                        self.registers[x] |= self.registers[y];
-                    },
-                    0x0002 => {
-                        println!("V{:01x} &= V{:01x}", (0x0F00 & opcode) >> 8,  (0x00F0 & opcode) >> 4);
-                        let x = ((0x0F00 & opcode) >> 8) as usize;
-                        let y = ((0x00F0 & opcode) >> 4) as usize;
-                        self.registers[x] &= self.registers[y];
-                    },
-                    0x0003 => {
-                        println!("V{:01x} ^= V{:01x}", (0x0F00 & opcode) >> 8,  (0x00F0 & opcode) >> 4);
-                        let x = ((0x0F00 & opcode) >> 8) as usize;
-                        let y = ((0x00F0 & opcode) >> 4) as usize;
-                        self.registers[x] ^= self.registers[y];
-                    },
-                    0x0004 => {
-                        println!("V{:01x} += V{:01x}", (0x0F00 & opcode) >> 8,  (0x00F0 & opcode) >> 4);
-                        let x = ((0x0F00 & opcode) >> 8) as usize;
-                        let y = ((0x00F0 & opcode) >> 4) as usize;
-                        self.registers[0xF] = self.registers[x].checked_add(self.registers[y]).is_none() as u8;
-                        self.registers[x] = self.registers[x].wrapping_add(self.registers[y]);
-                    },
-                    0x0005 => {
-                        println!("V{:01x} -= V{:01x}", (0x0F00 & opcode) >> 8,  (0x00F0 & opcode) >> 4);
-                        let x = ((0x0F00 & opcode) >> 8) as usize;
-                        let y = ((0x00F0 & opcode) >> 4) as usize;
-                        self.registers[0xF] = self.registers[x].checked_sub(self.registers[y]).is_some() as u8;
-                        self.registers[x] = self.registers[x].wrapping_sub(self.registers[y]);
-                    },
-                    0x0006 => {
-                        println!("V{:01x} >>= 1", (0x0F00 & opcode) >> 8);
-                        let x = ((0x0F00 & opcode) >> 8) as usize;
-                        self.registers[0xF] = self.registers[x] & 0b00000001;
-                        self.registers[x] >>= 1;
-                    },
-                    0x0007 => {
-                        let x = ((0x0F00 & opcode) >> 8) as usize;
-                        let y = ((0x00F0 & opcode) >> 4) as usize;
-                        println!("V{:01x} = V{:01x} - V{:01x}", (0x0F00 & opcode) >> 8,  (0x00F0 & opcode) >> 4, (0x0F00 & opcode) >> 8);
-                        self.registers[0xF] = self.registers[y].checked_sub(self.registers[x]).is_some() as u8;
-                        self.registers[x] = self.registers[y].wrapping_sub(self.registers[x]);
+            },
+            // machine code routines can't run here, so like modern interpreters skip them
+            Instruction::MachineCall(_) => {},
+            Instruction::Jump(nnn) => self.program_counter = nnn,
+            Instruction::Call(_) if self.stack.len() == STACK_DEPTH => {
+                self.program_counter -= 2;
+                self.fault = Some(Fault::StackOverflow { address: self.program_counter });
+            },
+            Instruction::Call(nnn) => {
+                self.stack.push(self.program_counter);
+                self.program_counter = nnn;
+            },
+            Instruction::SkipEqual(x, nn) => {
+                if self.registers[x as usize] == nn {
+                    self.program_counter += 2;
+                }
+            },
+            Instruction::SkipNotEqual(x, nn) => {
+                if self.registers[x as usize] != nn {
+                    self.program_counter += 2;
+                }
+            },
+            Instruction::SkipRegistersEqual(x, y) => {
+                if self.registers[x as usize] == self.registers[y as usize] {
+                    self.program_counter += 2;
+                }
+            },
+            Instruction::Set(x, nn) => self.registers[x as usize] = nn,
+            Instruction::Add(x, nn) => {
+                let x = x as usize;
+                self.registers[x] = self.registers[x].wrapping_add(nn);
+            },
+            Instruction::Move(x, y) => self.registers[x as usize] = self.registers[y as usize],
+            Instruction::Or(x, y) | Instruction::And(x, y) | Instruction::Xor(x, y) => {
+                let (x, y) = (x as usize, y as usize);
+                match instruction {
+                    Instruction::Or(..) => self.registers[x] |= self.registers[y],
+                    Instruction::And(..) => self.registers[x] &= self.registers[y],
+                    _ => self.registers[x] ^= self.registers[y],
+                }
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
+                }
+            },
+            Instruction::AddRegisters(x, y) => {
+                let x = x as usize;
+                let (result, carry) = self.registers[x].overflowing_add(self.registers[y as usize]);
+                self.registers[x] = result;
+                self.registers[0xF] = carry as u8;
+            },
+            Instruction::Subtract(x, y) => {
+                let x = x as usize;
+                let (result, borrow) = self.registers[x].overflowing_sub(self.registers[y as usize]);
+                self.registers[x] = result;
+                self.registers[0xF] = !borrow as u8;
+            },
+            Instruction::ShiftRight(x, y) => {
+                let x = x as usize;
+                let value = if self.quirks.shift_uses_vy { self.registers[y as usize] } else { self.registers[x] };
+                self.registers[x] = value >> 1;
+                self.registers[0xF] = value & 0b00000001;
+            },
+            Instruction::SubtractReverse(x, y) => {
+                let x = x as usize;
+                let (result, borrow) = self.registers[y as usize].overflowing_sub(self.registers[x]);
+                self.registers[x] = result;
+                self.registers[0xF] = !borrow as u8;
+            },
+            Instruction::ShiftLeft(x, y) => {
+                let x = x as usize;
+                let value = if self.quirks.shift_uses_vy { self.registers[y as usize] } else { self.registers[x] };
+                self.registers[x] = value << 1;
+                self.registers[0xF] = value >> 7;
+            },
+            Instruction::SkipRegistersNotEqual(x, y) => {
+                if self.registers[x as usize] != self.registers[y as usize] {
+                    self.program_counter += 2;
+                }
+            },
+            Instruction::SetIndex(nnn) => self.index_register = nnn,
+            Instruction::JumpOffset(x, nnn) => {
+                let x = if self.quirks.jump_uses_vx { x as usize } else { 0 };
+                self.program_counter = self.registers[x] as u16 + nnn;
+            },
+            Instruction::Random(x, nn) => {
+                self.registers[x as usize] = self.rng.gen_range(0..=u8::MAX) & nn;
+            },
+            Instruction::Draw(x, y, n) => {
+                let n = n as usize;
+                let mut sprite = [0; 15];
+                for (offset, row) in sprite[..n].iter_mut().enumerate() {
+                    *row = self.memory[(self.index_register as usize + offset) % MEMORY_SIZE];
+                }
+                self.registers[0xF] = self.framebuffer.draw(self.registers[x as usize] as usize, self.registers[y as usize] as usize, &sprite[..n], self.quirks.horizontal_edge, self.quirks.vertical_edge) as u8;
+            },
+            Instruction::SkipKeyPressed(x) => {
+                let key = Key::ALL[(self.registers[x as usize] & 0xF) as usize];
+                if self.input.is_key_pressed(key) {
+                    self.program_counter += 2;
+                }
+            },
+            Instruction::SkipKeyNotPressed(x) => {
+                let key = Key::ALL[(self.registers[x as usize] & 0xF) as usize];
+                if !self.input.is_key_pressed(key) {
+                    self.program_counter += 2;
+                }
+            },
+            Instruction::GetDelay(x) => self.registers[x as usize] = self.timers.delay_timer,
+            Instruction::WaitKey(x) => {
+                if !self.waiting_for_key {
+                    self.input.clear_key_release();
+                    self.waiting_for_key = true;
+                }
+                match self.input.take_key_release() {
+                    Some(key) => {
+                        self.registers[x as usize] = key as u8;
+                        self.waiting_for_key = false;
                     },
-                    0x000E => {
-                        println!("V{:01x} <<= 1", (0x0F00 & opcode) >> 8);
-                        let x = ((0x0F00 & opcode) >> 8) as usize;
-                        self.registers[0xF] = self.registers[x] & 0b10000000;
-                        self.registers[x] <<= 1;
+                    None => self.program_counter -= 2,
+                }
+            },
+            Instruction::SetDelay(x) => self.timers.delay_timer = self.registers[x as usize],
+            Instruction::SetSound(x) => self.timers.sound_timer = self.registers[x as usize],
+            Instruction::AddIndex(x) => {
+                self.index_register = self.index_register.wrapping_add(self.registers[x as usize] as u16);
+            },
+            Instruction::FontCharacter(x) => {
+                self.index_register = FONT_RANGE.start as u16 + 5 * (self.registers[x as usize] & 0xF) as u16;
+            },
+            Instruction::StoreBcd(x) => {
+                let value = self.registers[x as usize];
+                for (offset, digit) in [value / 100 % 10, value / 10 % 10, value % 10].into_iter().enumerate() {
+                    self.write_memory(self.index_register as usize + offset, digit);
+                }
+            },
+            Instruction::StoreRegisters(x) => {
+                for offset in 0..=x as usize {
+                    self.write_memory(self.index_register as usize + offset, self.registers[offset]);
+                }
+                if self.quirks.increment_index {
+                    self.index_register = self.index_register.wrapping_add(x as u16 + 1);
+                }
+            },
+            Instruction::LoadRegisters(x) => {
+                for (offset, register) in self.registers[..=x as usize].iter_mut().enumerate() {
+                    *register = self.memory[(self.index_register as usize + offset) % MEMORY_SIZE];
+                }
+                if self.quirks.increment_index {
+                    self.index_register = self.index_register.wrapping_add(x as u16 + 1);
+                }
+            },
+            Instruction::Invalid => {
+                self.program_counter -= 2;
+                let address = self.program_counter;
+                let opcode = self.opcode(address as usize).unwrap_or_default();
+                self.fault = Some(Fault::InvalidOpcode { address, opcode });
+            },
+        }
+    }
+}
+#[derive(Clone, Default)]
+pub struct Frame {
+    pub framebuffer: Framebuffer,
+    pub sound: bool,
+}
+
+enum Control {
+    Pause(bool),
+    Step,
+    Speed(f64),
+    Stop,
+}
+
+pub struct Processor {
+    control_tx: Sender<Control>,
+    frame_rx: Receiver<Result<Frame, Fault>>,
+    thread: Option<JoinHandle<()>>,
+}
+impl Processor {
+    pub fn new(input: InputReceiver, program: &[u8], config: &Config) -> io::Result<Self> {
+        let mut state = ProcessorState::new(input, program, config)?;
+
+        let (control_tx, control_rx) = mpsc::channel();
+        let (frame_tx, frame_rx) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            let mut paused = false;
+            let mut halted = false;
+            let mut steps = 0;
+            let mut frame_duration = FRAME_DURATION;
+            let mut next_frame = Instant::now();
+            loop {
+                let control = if halted || (paused && steps == 0) {
+                    Some(control_rx.recv().unwrap_or(Control::Stop))
+                } else {
+                    match control_rx.recv_timeout(next_frame.saturating_duration_since(Instant::now())) {
+                        Ok(control) => Some(control),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => Some(Control::Stop),
+                    }
+                };
+                match control {
+                    Some(Control::Pause(pause)) => {
+                        paused = pause;
+                        next_frame = Instant::now();
                     },
-                    _ => panic!("unknown opcode {:#06x}", opcode),
-                },
-                0x9000 => match opcode & 0x000F {
-                    0x0000 => { 
-                        println!("if (V{:01x} != V{:01x})", (0x0F00 & opcode) >> 8,  (0x00F0 & opcode) >> 4);
-                        let x = ((0x0F00 & opcode) >> 8) as usize;
-                        let y = ((0x00F0 & opcode) >> 4) as usize;
-                        if self.registers[x] != self.registers[y] {
-                            self.program_counter += 2;
+                    Some(Control::Step) => steps += 1,
+                    Some(Control::Speed(speed)) if speed > 0.0 => frame_duration = FRAME_DURATION.div_f64(speed),
+                    Some(Control::Speed(_)) => {},
+                    Some(Control::Stop) => break,
+                    None => {
+                        if paused {
+                            steps -= 1;
                         }
-                    },
-                    _ => panic!("unknown opcode {:#06x}", opcode),
-                },
-                0xA000 => {
-                    println!("I = {:#05x}", 0x0FFF & opcode);
-                    let nnn = 0x0FFF & opcode;
-                    self.index_register = nnn;
-                },
-                0xB000 => {
-                    println!("PC = V0 + {:#05x}", 0x0FFF & opcode);
-                    let nnn = 0x0FFF & opcode;
-                    self.program_counter = self.registers[0] as u16 + nnn;
-                },
-                0xC000 => {
-                    println!("V{:01x} = rand() & {:#04x}", (0x0F00 & opcode) >> 8, 0x00FF & opcode);
-                    let x = ((0x0F00 & opcode) >> 8) as usize;
-                    let nn = (0x00FF & opcode) as u8;
-                    self.registers[x] = rand::thread_rng().gen_range(0..=u8::MAX) & nn;
-                },
-                0xD000 => {
-                    println!("draw(V{:01x}, V{:01x}, {:#04x})", (0x0F00 & opcode) >> 8,  (0x00F0 & opcode) >> 4, 0x000F & opcode);
-                    let x = ((0x0F00 & opcode) >> 8) as usize;
-                    let y = ((0x00F0 & opcode) >> 4) as usize;
-                    let sprite = &self.memory[self.index_register as usize..(self.index_register + (0x000F & opcode)) as usize];
-                    self.registers[0xF] = self.display.lock().unwrap().draw(self.registers[x] as usize, self.registers[y] as usize, sprite) as u8;
-                },
-                0xE000 => match opcode & 0x00FF { 
-                    0x009E => {
-                        println!("if (key() == V{:01x})", (0x0F00 & opcode) >> 8);
-                        let x = ((0x0F00 & opcode) >> 8) as usize;
-                        let input = self.input.lock().unwrap();
-                        let key = unsafe { std::mem::transmute(self.registers[x]) };
-                        if input.is_key_pressed(key) {
-                            self.program_counter += 2;
+                        // a fault halts the machine after its last frame until it is stopped
+                        let result = state.run_frame(Instant::now());
+                        let mut sent = frame_tx.send(Ok(state.take_frame()));
+                        if let Err(fault) = result {
+                            halted = true;
+                            sent = sent.and_then(|()| frame_tx.send(Err(fault)));
                         }
-                    },
-                    0x00A1 => {
-                        println!("if (key() != V{:01x})", (0x0F00 & opcode) >> 8);
-                        let x = ((0x0F00 & opcode) >> 8) as usize;
-                        let input = self.input.lock().unwrap();
-                        let key = unsafe { std::mem::transmute(self.registers[x]) };
-                        if !input.is_key_pressed(key) {
-                            self.program_counter += 2;
+                        if sent.is_err() {
+                            break;
                         }
-                    },
-                    _ => panic!("unknown opcode {:#06x}", opcode),
-                },
-                0xF000 => match opcode & 0x00FF { 
-                    0x0007 => {
-                        println!("V{:01x} = get_delay()", (0x0F00 & opcode) >> 8);
-                        let x = ((0x0F00 & opcode) >> 8) as usize;
-                        let delay_timer = *self.timers.delay_timer.lock().unwrap();
-                        self.registers[x] = delay_timer;
-                    },
-                    0x000A => {
-                        println!("V{:01x} = get_key()", (0x0F00 & opcode) >> 8);
-                        let x = ((0x0F00 & opcode) >> 8) as usize;
-                        let input = self.input.lock().unwrap();
-                        let key = input.recv_key_press().unwrap() as u8;
-                        self.registers[x] = key;
-                    },
-                    0x0015 => {
-                        println!("delay_timer(V{:01x})", (0x0F00 & opcode) >> 8);
-                        let x = ((0x0F00 & opcode) >> 8) as usize;
-                        let mut delay_timer = self.timers.delay_timer.lock().unwrap();
-                        *delay_timer = self.registers[x];
-                    },
-                    0x0018 => {
-                        println!("sound_timer(V{:01x})", (0x0F00 & opcode) >> 8);
-                        let x = ((0x0F00 & opcode) >> 8) as usize;
-                        let mut sound_timer = self.timers.sound_timer.lock().unwrap();
-                        *sound_timer = self.registers[x];
-                    },
-                    0x001E => {
-                        println!("I += V{:01x}", (0x0F00 & opcode) >> 8);
-                        let x = ((0x0F00 & opcode) >> 8) as usize;
-                        self.index_register = self.index_register.wrapping_add(self.registers[x] as u16);
-                    },
-                    0x0029 => {
-                        println!("I = sprite_addr[V{:01x}]", (0x0F00 & opcode) >> 8);
-                        let x = ((0x0F00 & opcode) >> 8) as usize;
-                        self.index_register = FONT_RANGE.start as u16 + 5 * self.registers[x] as u16;
-                    },
-                    0x0033 => { 
-                        println!("set_BCD(V{:01x})", (0x0F00 & opcode) >> 8);
-                        println!("*(I+0) = BCD(3)");
-                        println!("*(I+1) = BCD(2)");
-                        println!("*(I+2) = BCD(1)");
-                        let x = ((0x0F00 & opcode) >> 8) as usize;
-                        self.memory[self.index_register as usize] = self.registers[x] / 100 % 10;
-                        self.memory[self.index_register as usize + 1] = self.registers[x] / 10 % 10;
-                        self.memory[self.index_register as usize + 2] = self.registers[x] % 10;
-                    },
-                    0x0055 => { 
-                        println!("reg_dump(V{:01x}, &I)", (0x0F00 & opcode) >> 8);
-                        let x = ((0x0F00 & opcode) >> 8) as usize;
-                        let memory_iter = (&mut self.memory[self.index_register as usize..=self.index_register as usize + x]).iter_mut();
-                        let register_iter = self.registers.iter();
-                        for (memory, register) in memory_iter.zip(register_iter) {
-                            *memory = *register;
-                        }
-                    },
-                    0x0065 => { 
-                        println!("reg_load(V{:01x}, &I)", (0x0F00 & opcode) >> 8);
-                        let x = ((0x0F00 & opcode) >> 8) as usize;
-                        let register_iter = self.registers.iter_mut();
-                        let memory_iter = (&self.memory[self.index_register as usize..=self.index_register as usize + x]).iter();
-                        for (register, memory) in register_iter.zip(memory_iter) {
-                            *register = *memory;
+
+                        next_frame += frame_duration;
+                        let now = Instant::now();
+                        if next_frame < now {
+                            next_frame = now;
                         }
                     },
-                    _ => panic!("unknown opcode {:#06x}", opcode),
-                },
-                _ => panic!("unknown opcode {:#06x}", opcode)
+                }
             }
-        }
+        });
+
+        Ok(Self {
+            control_tx,
+            frame_rx,
+            thread: Some(thread),
+        })
+    }
+    pub fn set_paused(&self, paused: bool) {
+        let _ = self.control_tx.send(Control::Pause(paused));
+    }
+    pub fn step(&self) {
+        let _ = self.control_tx.send(Control::Step);
+    }
+    pub fn set_speed(&self, speed: f64) {
+        let _ = self.control_tx.send(Control::Speed(speed));
+    }
+    pub fn frames(&self) -> TryIter<'_, Result<Frame, Fault>> {
+        self.frame_rx.try_iter()
+    }
+    // the thread only ends early if it panicked
+    pub fn crashed(&self) -> bool {
+        self.thread.as_ref().is_some_and(|thread| thread.is_finished())
     }
 }
-pub struct Processor {
-    _timer: Timer,
-    _guard: Guard,
+impl Drop for Processor {
+    fn drop(&mut self) {
+        let _ = self.control_tx.send(Control::Stop);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
 }
-impl Processor {
-    pub fn new(input: Arc<Mutex<InputReceiver>>, display: Arc<Mutex<Display>>, program: &[u8]) -> Self {
-        let mut state = ProcessorState::new(input, display, program);
 
-        let _timer = timer::Timer::new();
-        let _guard = _timer.schedule_repeating(Duration::microseconds(1), move || {
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{input::{self, InputSender, KeyState}, quirks::QuirkPreset};
+
+    const PRESETS: [QuirkPreset; 3] = [QuirkPreset::Chip8, QuirkPreset::Superchip, QuirkPreset::Xochip];
+
+    fn machine(program: &[u16], quirks: QuirkPreset) -> (InputSender, ProcessorState) {
+        machine_with(program, &Config {
+            quirks,
+            ..Config::default()
+        })
+    }
+
+    fn machine_with(program: &[u16], config: &Config) -> (InputSender, ProcessorState) {
+        let program: Vec<u8> = program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+        let (input_tx, input_rx) = input::input(false);
+        let config = Config {
+            seed: Some(0),
+            ..config.clone()
+        };
+        (input_tx, ProcessorState::new(input_rx, &program, &config).unwrap())
+    }
+
+    // runs until the program counter leaves the program
+    fn run(program: &[u16], quirks: QuirkPreset) -> ProcessorState {
+        let (_input_tx, mut state) = machine(program, quirks);
+        let end = 0x200 + 2 * program.len() as u16;
+        while state.program_counter < end && state.fault.is_none() {
             state.process();
+        }
+        state
+    }
+
+    #[test]
+    fn arithmetic_flag_wins_when_vf_is_the_destination() {
+        // 8XY4 with and without a carry
+        assert_eq!(run(&[0x6FFF, 0x6102, 0x8F14], QuirkPreset::Chip8).registers[0xF], 1);
+        assert_eq!(run(&[0x6F01, 0x6101, 0x8F14], QuirkPreset::Chip8).registers[0xF], 0);
+        // 8XY5 without and with a borrow
+        assert_eq!(run(&[0x6F05, 0x6103, 0x8F15], QuirkPreset::Chip8).registers[0xF], 1);
+        assert_eq!(run(&[0x6F03, 0x6105, 0x8F15], QuirkPreset::Chip8).registers[0xF], 0);
+        // 8XY7 without and with a borrow
+        assert_eq!(run(&[0x6F03, 0x6105, 0x8F17], QuirkPreset::Chip8).registers[0xF], 1);
+        assert_eq!(run(&[0x6F05, 0x6103, 0x8F17], QuirkPreset::Chip8).registers[0xF], 0);
+        // 8XY6 and 8XYE shifting VF itself
+        assert_eq!(run(&[0x6F03, 0x8F06], QuirkPreset::Superchip).registers[0xF], 1);
+        assert_eq!(run(&[0x6F02, 0x8F06], QuirkPreset::Superchip).registers[0xF], 0);
+        assert_eq!(run(&[0x6F80, 0x8F0E], QuirkPreset::Superchip).registers[0xF], 1);
+        assert_eq!(run(&[0x6F40, 0x8F0E], QuirkPreset::Superchip).registers[0xF], 0);
+        // shifting VY into VF with the chip-8 quirk
+        assert_eq!(run(&[0x6181, 0x8F16], QuirkPreset::Chip8).registers[0xF], 1);
+        assert_eq!(run(&[0x6141, 0x8F1E], QuirkPreset::Chip8).registers[0xF], 0);
+    }
+
+    #[test]
+    fn arithmetic_results() {
+        let state = run(&[0x60F0, 0x6120, 0x8014], QuirkPreset::Chip8);
+        assert_eq!((state.registers[0], state.registers[0xF]), (0x10, 1));
+        let state = run(&[0x6010, 0x6120, 0x8015], QuirkPreset::Chip8);
+        assert_eq!((state.registers[0], state.registers[0xF]), (0xF0, 0));
+        let state = run(&[0x6010, 0x6120, 0x8017], QuirkPreset::Chip8);
+        assert_eq!((state.registers[0], state.registers[0xF]), (0x10, 1));
+        let state = run(&[0x6081, 0x8006], QuirkPreset::Superchip);
+        assert_eq!((state.registers[0], state.registers[0xF]), (0x40, 1));
+    }
+
+    #[test]
+    fn shift_left_sets_vf_to_one() {
+        let state = run(&[0x6081, 0x800E], QuirkPreset::Superchip);
+        assert_eq!((state.registers[0], state.registers[0xF]), (0x02, 1));
+    }
+
+    #[test]
+    fn key_skips_use_the_low_nibble_of_vx() {
+        let (input_tx, mut state) = machine(&[0x6015, 0xE09E, 0x0000, 0x601A, 0xE0A1, 0x0000], QuirkPreset::Chip8);
+        input_tx.send_key(Key::Key5, KeyState::Pressed).unwrap();
+        state.input.process_key_events(Instant::now());
+        state.process();
+        state.process();
+        assert_eq!(state.program_counter, 0x206);
+        state.process();
+        state.process();
+        assert_eq!(state.program_counter, 0x20C);
+
+        let (_input_tx, mut state) = machine(&[0x6015, 0xE0A1], QuirkPreset::Chip8);
+        state.process();
+        state.process();
+        assert_eq!(state.program_counter, 0x206);
+    }
+
+    #[test]
+    fn font_character_masks_the_low_nibble() {
+        let state = run(&[0x601A, 0xF029], QuirkPreset::Chip8);
+        assert_eq!(state.index_register, FONT_RANGE.start as u16 + 5 * 0xA);
+    }
+
+    #[test]
+    fn memory_access_wraps_at_the_end_of_memory() {
+        for preset in PRESETS {
+            let state = run(&[0x607B, 0xAFFE, 0xF033], preset);
+            assert_eq!([state.memory[0xFFE], state.memory[0xFFF], state.memory[0x000]], [1, 2, 3], "{:?}", preset);
+
+            let state = run(&[0x6011, 0x6122, 0x6233, 0xAFFF, 0xF255], preset);
+            assert_eq!([state.memory[0xFFF], state.memory[0x000], state.memory[0x001]], [0x11, 0x22, 0x33], "{:?}", preset);
+            let expected = if Quirks::from(preset).increment_index { 0x1002 } else { 0xFFF };
+            assert_eq!(state.index_register, expected, "{:?}", preset);
+
+            let (_input_tx, mut state) = machine(&[0xAFFF, 0xF265], preset);
+            state.memory[0xFFF] = 0x44;
+            state.memory[0x000] = 0x55;
+            state.memory[0x001] = 0x66;
+            state.process();
+            state.process();
+            assert_eq!(state.registers[..3], [0x44, 0x55, 0x66], "{:?}", preset);
+        }
+    }
+
+    #[test]
+    fn index_increment_wraps_past_0xffff() {
+        for preset in PRESETS {
+            let (_input_tx, mut state) = machine(&[0xF255, 0xF265], preset);
+            state.index_register = 0xFFFE;
+            state.process();
+            state.index_register = 0xFFFE;
+            state.process();
+            let expected = if Quirks::from(preset).increment_index { 0x0001 } else { 0xFFFE };
+            assert_eq!(state.index_register, expected, "{:?}", preset);
+        }
+    }
+
+    #[test]
+    fn machine_calls_are_skipped() {
+        let state = run(&[0x0000, 0x0123, 0x6005], QuirkPreset::Chip8);
+        assert_eq!(state.registers[0], 5);
+        assert_eq!(state.fault, None);
+    }
+
+    #[test]
+    fn return_without_a_subroutine_halts_with_a_fault() {
+        let (_input_tx, mut state) = machine(&[0x6005, 0x00EE], QuirkPreset::Chip8);
+        assert_eq!(state.run_frame(Instant::now()), Err(Fault::StackUnderflow { address: 0x202 }));
+        assert_eq!(state.program_counter, 0x202);
+    }
+
+    #[test]
+    fn calls_past_the_stack_depth_halt_with_a_fault() {
+        let (_input_tx, mut state) = machine(&[0x2200], QuirkPreset::Chip8);
+        for _ in 0..STACK_DEPTH {
+            state.process();
+        }
+        assert_eq!(state.fault, None);
+        assert_eq!(state.run_frame(Instant::now()), Err(Fault::StackOverflow { address: 0x200 }));
+        assert_eq!(state.stack.len(), STACK_DEPTH);
+        assert_eq!(state.program_counter, 0x200);
+    }
+
+    #[test]
+    fn leaving_memory_halts_with_a_fault() {
+        for engine in [Engine::Interpreter, Engine::Recompiler] {
+            let config = Config {
+                engine,
+                ..Config::default()
+            };
+            // a computed jump past the end of memory
+            let (_input_tx, mut state) = machine_with(&[0x60FF, 0xBFFF], &config);
+            assert_eq!(state.run_frame(Instant::now()), Err(Fault::OutOfMemory { address: 0x10FE }), "{:?}", engine);
+            // running off the end through the zeroes after the program
+            let (_input_tx, mut state) = machine_with(&[0x1FFC], &config);
+            assert_eq!(state.run_frame(Instant::now()), Err(Fault::OutOfMemory { address: 0x1000 }), "{:?}", engine);
+        }
+        let (_input_tx, mut state) = machine_with(&[0x1FFE], &Config {
+            timing: Timing::Vip,
+            ..Config::default()
         });
+        assert_eq!(state.run_frame(Instant::now()), Err(Fault::OutOfMemory { address: 0x1000 }));
+    }
+
+    #[test]
+    fn clear_blanks_the_display() {
+        let state = run(&[0xA050, 0xD015, 0x00E0], QuirkPreset::Chip8);
+        assert!((0..8).all(|x| (0..5).all(|y| !state.framebuffer.pixel(x, y))));
+    }
+
+    #[test]
+    fn jumps_and_subroutines() {
+        let state = run(&[0x1206, 0x6001, 0x6002, 0x6003], QuirkPreset::Chip8);
+        assert_eq!(state.registers[0], 3);
+
+        // call 0x206, which sets V1 and returns to set V0 and jump past the end
+        let program = [0x2206, 0x6001, 0x120A, 0x6105, 0x00EE];
+        let (_input_tx, mut state) = machine(&program, QuirkPreset::Chip8);
+        state.process();
+        assert_eq!((state.program_counter, state.stack.as_slice()), (0x206, [0x202].as_slice()));
+        let state = run(&program, QuirkPreset::Chip8);
+        assert_eq!((state.registers[0], state.registers[1]), (1, 5));
+        assert!(state.stack.is_empty());
+    }
+
+    #[test]
+    fn conditional_skips() {
+        // 3XNN and 4XNN skip when VX is and isn't NN
+        let state = run(&[0x6005, 0x3005, 0x6101, 0x3006, 0x6201], QuirkPreset::Chip8);
+        assert_eq!((state.registers[1], state.registers[2]), (0, 1));
+        let state = run(&[0x6005, 0x4005, 0x6101, 0x4006, 0x6201], QuirkPreset::Chip8);
+        assert_eq!((state.registers[1], state.registers[2]), (1, 0));
+        // 5XY0 and 9XY0 skip when VX and VY are and aren't equal
+        let state = run(&[0x6005, 0x6105, 0x5010, 0x6201, 0x6306, 0x5030, 0x6401], QuirkPreset::Chip8);
+        assert_eq!((state.registers[2], state.registers[4]), (0, 1));
+        let state = run(&[0x6005, 0x6105, 0x9010, 0x6201, 0x6306, 0x9030, 0x6401], QuirkPreset::Chip8);
+        assert_eq!((state.registers[2], state.registers[4]), (1, 0));
+    }
+
+    #[test]
+    fn load_and_add_leave_vf_alone() {
+        let state = run(&[0x6F05, 0x60FF, 0x7002], QuirkPreset::Chip8);
+        assert_eq!((state.registers[0], state.registers[0xF]), (1, 5));
+    }
+
+    #[test]
+    fn logic_resets_vf_with_the_quirk() {
+        for preset in PRESETS {
+            let state = run(&[0x600C, 0x610A, 0x6F07, 0x8010], preset);
+            assert_eq!((state.registers[0], state.registers[0xF]), (0x0A, 7), "{:?}", preset);
+            let vf = if Quirks::from(preset).vf_reset { 0 } else { 7 };
+            for (opcode, expected) in [(0x8011, 0x0E), (0x8012, 0x08), (0x8013, 0x06)] {
+                let state = run(&[0x600C, 0x610A, 0x6F07, opcode], preset);
+                assert_eq!((state.registers[0], state.registers[0xF]), (expected, vf), "{:04x} {:?}", opcode, preset);
+            }
+        }
+    }
+
+    #[test]
+    fn index_loads_and_adds() {
+        assert_eq!(run(&[0xA123], QuirkPreset::Chip8).index_register, 0x123);
+        assert_eq!(run(&[0xA0FF, 0x6002, 0xF01E], QuirkPreset::Chip8).index_register, 0x101);
+    }
+
+    #[test]
+    fn jump_offset_uses_v0_or_vx_with_the_quirk() {
+        for preset in PRESETS {
+            let (_input_tx, mut state) = machine(&[0x6010, 0x6120, 0xB130], preset);
+            for _ in 0..3 {
+                state.process();
+            }
+            let expected = if Quirks::from(preset).jump_uses_vx { 0x150 } else { 0x140 };
+            assert_eq!(state.program_counter, expected, "{:?}", preset);
+        }
+    }
+
+    #[test]
+    fn random_is_masked_and_follows_the_seed() {
+        let state = run(&[0xC00F, 0xC1F0], QuirkPreset::Chip8);
+        let mut rng = StdRng::seed_from_u64(0);
+        let expected = [rng.gen_range(0..=u8::MAX) & 0x0F, rng.gen_range(0..=u8::MAX) & 0xF0];
+        assert_eq!(state.registers[..2], expected);
+    }
 
-        Self {
-            _timer,
-            _guard,
+    #[test]
+    fn draw_reports_collisions_in_vf() {
+        let (_input_tx, mut state) = machine(&[0xA050, 0xD015, 0xD015], QuirkPreset::Chip8);
+        state.process();
+        state.process();
+        assert_eq!(state.registers[0xF], 0);
+        assert!(state.framebuffer.pixel(0, 0));
+        state.process();
+        assert_eq!(state.registers[0xF], 1);
+        assert!(!state.framebuffer.pixel(0, 0));
+    }
+
+    #[test]
+    fn timers_load_and_store() {
+        let state = run(&[0x6042, 0xF015, 0x6017, 0xF018, 0xF107], QuirkPreset::Chip8);
+        assert_eq!((state.timers.delay_timer, state.timers.sound_timer), (0x42, 0x17));
+        assert_eq!(state.registers[1], 0x42);
+    }
+
+    #[test]
+    fn wait_key_completes_on_release() {
+        let (input_tx, mut state) = machine(&[0xF30A, 0x6001], QuirkPreset::Chip8);
+        state.process();
+        assert_eq!(state.program_counter, 0x200);
+        input_tx.send_key(Key::Key7, KeyState::Pressed).unwrap();
+        state.input.process_key_events(Instant::now());
+        state.process();
+        assert_eq!(state.program_counter, 0x200);
+        input_tx.send_key(Key::Key7, KeyState::Released).unwrap();
+        state.input.process_key_events(Instant::now());
+        state.process();
+        assert_eq!((state.program_counter, state.registers[3]), (0x202, 7));
+        assert!(!state.waiting_for_key);
+    }
+
+    fn vip_machine(program: &[u16]) -> ProcessorState {
+        machine_with(program, &Config {
+            timing: Timing::Vip,
+            ..Config::default()
+        }).1
+    }
+
+    #[test]
+    fn vip_timing_runs_a_frame_of_cycles() {
+        let budget = VIP_CYCLES_PER_FRAME - VIP_DISPLAY_CYCLES;
+        let (add, jump) = (timing::vip_cycles(Instruction::Add(0, 1), &[0; 16]) as i64, timing::vip_cycles(Instruction::Jump(0x200), &[0; 16]) as i64);
+        let mut state = vip_machine(&[0x7001, 0x1200]);
+        state.run_frame(Instant::now()).unwrap();
+        // the last instruction starts with cycles left and overruns into the next frame
+        let loops = budget / (add + jump);
+        assert_eq!(state.instructions, 2 * loops as u64 + 1);
+        assert_eq!(state.cycles, budget - loops * (add + jump) - add);
+        assert!(state.cycles <= 0 && state.cycles > -add);
+    }
+
+    #[test]
+    fn vip_timing_ends_the_frame_at_a_draw() {
+        let mut state = vip_machine(&[0xA050, 0xD015, 0x1202]);
+        state.run_frame(Instant::now()).unwrap();
+        assert_eq!((state.instructions, state.program_counter, state.cycles), (2, 0x204, 0));
+        state.run_frame(Instant::now()).unwrap();
+        assert_eq!((state.instructions, state.program_counter, state.cycles), (4, 0x204, 0));
+    }
+
+    #[test]
+    fn vip_timing_carries_long_instructions_into_the_next_frame() {
+        let budget = VIP_CYCLES_PER_FRAME - VIP_DISPLAY_CYCLES;
+        let clear = timing::vip_cycles(Instruction::Clear, &[0; 16]) as i64;
+        let jump = timing::vip_cycles(Instruction::Jump(0x202), &[0; 16]) as i64;
+        assert!(clear > budget);
+        let mut state = vip_machine(&[0x00E0, 0x1202]);
+        state.run_frame(Instant::now()).unwrap();
+        assert_eq!((state.instructions, state.cycles), (1, budget - clear));
+        // the second frame only has what the clear left of its budget
+        state.run_frame(Instant::now()).unwrap();
+        let jumps = (2 * budget - clear + jump - 1) / jump;
+        assert_eq!(state.instructions, 1 + jumps as u64);
+        assert!(jumps < (budget + jump - 1) / jump);
+    }
+
+    #[test]
+    fn emulation_thread_sends_the_fault_after_the_last_frame() {
+        let (input_tx, input_rx) = input::input(false);
+        let processor = Processor::new(input_rx, &[0x00, 0xEE], &Config::default()).unwrap();
+        let mut messages = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while messages.len() < 2 && Instant::now() < deadline {
+            messages.extend(processor.frames());
+            thread::sleep(Duration::from_millis(1));
         }
+        assert_eq!(messages.len(), 2, "timed out waiting for the frame and the fault");
+        assert!(messages[0].is_ok());
+        assert_eq!(messages[1].as_ref().err(), Some(&Fault::StackUnderflow { address: 0x200 }));
+        // the halted thread keeps the input open and doesn't run any further
+        thread::sleep(FRAME_DURATION * 3);
+        assert_eq!(processor.frames().count(), 0);
+        assert!(input_tx.send_key(Key::Key1, KeyState::Pressed).is_ok());
+        assert!(!processor.crashed());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn invalid_opcodes_halt_with_a_fault() {
+        let (_input_tx, mut state) = machine(&[0x6005, 0xFFFF, 0x6006], QuirkPreset::Chip8);
+        let fault = Fault::InvalidOpcode { address: 0x202, opcode: 0xFFFF };
+        assert_eq!(state.run_frame(Instant::now()), Err(fault));
+        assert_eq!(state.program_counter, 0x202);
+        assert_eq!(state.registers[0], 5);
+        assert_eq!(state.run_frame(Instant::now()), Err(fault));
+        assert_eq!(state.registers[0], 5);
+    }
+
+    #[test]
+    fn valid_opcodes_follow_the_decoder() {
+        for opcode in [0x00E0, 0x00EE, 0x1234, 0x5120, 0x812E, 0xE19E, 0xF165] {
+            assert!(is_valid_opcode(opcode), "{:04x}", opcode);
+        }
+        for opcode in [0x0000, 0x0123, 0x5121, 0x8128, 0xE19F, 0xF1FF] {
+            assert!(!is_valid_opcode(opcode), "{:04x}", opcode);
+        }
+    }
+
+    #[test]
+    fn writes_over_decoded_code_are_decoded_again() {
+        // V0 = 1, jump back
+        let (_input_tx, mut state) = machine(&[0x6001, 0x1200], QuirkPreset::Chip8);
+        state.process();
+        state.process();
+        assert!(state.decoded[0x200].is_some());
+        // rewrite the low byte, which drops the instruction it belongs to
+        state.write_memory(0x201, 0x07);
+        assert!(state.decoded[0x200].is_none());
+        state.process();
+        assert_eq!(state.registers[0], 7);
+    }
+
+    #[test]
+    fn idle_loops_end_the_frame_without_counting_skipped_jumps() {
+        let config = Config {
+            engine: Engine::Recompiler,
+            speed: 100,
+            ..Config::default()
+        };
+        let (_input_tx, mut state) = machine_with(&[0x6001, 0x1202], &config);
+        // the jump into the idle loop, then one pass of the loop per frame
+        state.run_frame(Instant::now()).unwrap();
+        assert_eq!((state.program_counter, state.instructions), (0x202, 3));
+        state.run_frame(Instant::now()).unwrap();
+        assert_eq!(state.instructions, 4);
+    }
+
+    #[test]
+    fn writes_over_compiled_code_recompile_the_block() {
+        let config = Config {
+            engine: Engine::Recompiler,
+            speed: 8,
+            ..Config::default()
+        };
+        // V0 = 1, V1 += 1, jump back
+        let (_input_tx, mut state) = machine_with(&[0x6001, 0x7101, 0x1200], &config);
+        state.run_frame(Instant::now()).unwrap();
+        assert!(state.blocks[0x200].is_some());
+        assert_eq!(state.registers[0], 1);
+
+        // even rewriting the last byte of the block with the same value drops it
+        state.write_memory(0x205, 0x00);
+        assert!(state.blocks[0x200].is_none());
+        state.write_memory(0x201, 0x07);
+        state.run_frame(Instant::now()).unwrap();
+        assert_eq!(state.registers[0], 7);
+        assert!(state.blocks[0x200].is_some());
+
+        // writes elsewhere keep it
+        state.write_memory(0x300, 0xFF);
+        assert!(state.blocks[0x200].is_some());
+    }
+}
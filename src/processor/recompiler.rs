@@ -0,0 +1,190 @@
+use serde::Deserialize;
+use crate::instruction::Instruction;
+use super::ProcessorState;
+
+const MAX_BLOCK_LENGTH: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Engine {
+    #[default]
+    Interpreter,
+    Recompiler,
+}
+
+type Op = Box<dyn Fn(&mut ProcessorState) + Send>;
+
+// a straight-line run of instructions ending at the first one that can leave it,
+// compiled into closures that each execute one instruction
+pub(super) struct Block {
+    pub(super) ops: Vec<Op>,
+    pub(super) ends_with_draw: bool,
+    // a lone jump to itself, which spins without changing any state until the frame ends
+    pub(super) idle: bool,
+}
+
+fn ends_block(instruction: Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Return | Instruction::MachineCall(_) | Instruction::Jump(_) | Instruction::Call(_)
+        | Instruction::SkipEqual(..) | Instruction::SkipNotEqual(..) | Instruction::SkipRegistersEqual(..)
+        | Instruction::SkipRegistersNotEqual(..) | Instruction::JumpOffset(..) | Instruction::Draw(..)
+        | Instruction::SkipKeyPressed(_) | Instruction::SkipKeyNotPressed(_) | Instruction::WaitKey(_)
+        // these write memory, which may hold the rest of the block
+        | Instruction::StoreBcd(_) | Instruction::StoreRegisters(_)
+        | Instruction::Invalid
+    )
+}
+
+pub(super) fn compile(state: &mut ProcessorState, start: usize) -> Option<Block> {
+    let mut ops = Vec::new();
+    let mut address = start;
+    let mut last = None;
+    while ops.len() < MAX_BLOCK_LENGTH {
+        let Some(instruction) = state.decode(address) else {
+            break;
+        };
+        // traced instructions go through the interpreter so they are written out
+        ops.push(match state.trace {
+            Some(_) => interpret(instruction),
+            None => op(state, instruction),
+        });
+        last = Some(instruction);
+        address += 2;
+        if ends_block(instruction) {
+            break;
+        }
+    }
+
+    let last = last?;
+    Some(Block {
+        ends_with_draw: matches!(last, Instruction::Draw(..)),
+        idle: ops.len() == 1 && last == Instruction::Jump(start as u16) && state.trace.is_none(),
+        ops,
+    })
+}
+
+// drops the compiled blocks that contain the instruction at address
+pub(super) fn invalidate(state: &mut ProcessorState, address: usize) {
+    for start in address.saturating_sub(2 * (MAX_BLOCK_LENGTH - 1))..=address {
+        if let Some(block) = &state.blocks[start] {
+            if start + 2 * block.ops.len() > address {
+                state.blocks[start] = None;
+            }
+        }
+    }
+}
+
+pub(super) fn intact(state: &ProcessorState, start: usize, block: &Block) -> bool {
+    (start..start + 2 * block.ops.len()).step_by(2).all(|address| state.decoded[address].is_some())
+}
+
+fn interpret(instruction: Instruction) -> Op {
+    Box::new(move |state| state.execute(instruction))
+}
+
+fn op(state: &ProcessorState, instruction: Instruction) -> Op {
+    let quirks = state.quirks;
+    match instruction {
+        Instruction::Jump(nnn) => Box::new(move |state| state.program_counter = nnn),
+        Instruction::SkipEqual(x, nn) => {
+            let x = x as usize;
+            Box::new(move |state| state.program_counter += if state.registers[x] == nn { 4 } else { 2 })
+        },
+        Instruction::SkipNotEqual(x, nn) => {
+            let x = x as usize;
+            Box::new(move |state| state.program_counter += if state.registers[x] != nn { 4 } else { 2 })
+        },
+        Instruction::SkipRegistersEqual(x, y) => {
+            let (x, y) = (x as usize, y as usize);
+            Box::new(move |state| state.program_counter += if state.registers[x] == state.registers[y] { 4 } else { 2 })
+        },
+        Instruction::SkipRegistersNotEqual(x, y) => {
+            let (x, y) = (x as usize, y as usize);
+            Box::new(move |state| state.program_counter += if state.registers[x] != state.registers[y] { 4 } else { 2 })
+        },
+        Instruction::Set(x, nn) => {
+            let x = x as usize;
+            Box::new(move |state| {
+                state.program_counter += 2;
+                state.registers[x] = nn;
+            })
+        },
+        Instruction::Add(x, nn) => {
+            let x = x as usize;
+            Box::new(move |state| {
+                state.program_counter += 2;
+                state.registers[x] = state.registers[x].wrapping_add(nn);
+            })
+        },
+        Instruction::Move(x, y) => {
+            let (x, y) = (x as usize, y as usize);
+            Box::new(move |state| {
+                state.program_counter += 2;
+                state.registers[x] = state.registers[y];
+            })
+        },
+        Instruction::Or(x, y) | Instruction::And(x, y) | Instruction::Xor(x, y) => {
+            let (x, y) = (x as usize, y as usize);
+            let operation: fn(u8, u8) -> u8 = match instruction {
+                Instruction::Or(..) => |a, b| a | b,
+                Instruction::And(..) => |a, b| a & b,
+                _ => |a, b| a ^ b,
+            };
+            let vf_reset = quirks.vf_reset;
+            Box::new(move |state| {
+                state.program_counter += 2;
+                state.registers[x] = operation(state.registers[x], state.registers[y]);
+                if vf_reset {
+                    state.registers[0xF] = 0;
+                }
+            })
+        },
+        Instruction::AddRegisters(x, y) => {
+            let (x, y) = (x as usize, y as usize);
+            Box::new(move |state| {
+                state.program_counter += 2;
+                let (result, carry) = state.registers[x].overflowing_add(state.registers[y]);
+                state.registers[x] = result;
+                state.registers[0xF] = carry as u8;
+            })
+        },
+        Instruction::Subtract(x, y) | Instruction::SubtractReverse(x, y) => {
+            let (x, y) = (x as usize, y as usize);
+            let (minuend, subtrahend) = match instruction {
+                Instruction::Subtract(..) => (x, y),
+                _ => (y, x),
+            };
+            Box::new(move |state| {
+                state.program_counter += 2;
+                let (result, borrow) = state.registers[minuend].overflowing_sub(state.registers[subtrahend]);
+                state.registers[x] = result;
+                state.registers[0xF] = !borrow as u8;
+            })
+        },
+        Instruction::ShiftRight(x, y) | Instruction::ShiftLeft(x, y) => {
+            let (x, y) = (x as usize, y as usize);
+            let source = if quirks.shift_uses_vy { y } else { x };
+            let right = matches!(instruction, Instruction::ShiftRight(..));
+            Box::new(move |state| {
+                state.program_counter += 2;
+                let value = state.registers[source];
+                let (result, flag) = if right { (value >> 1, value & 0b00000001) } else { (value << 1, value >> 7) };
+                state.registers[x] = result;
+                state.registers[0xF] = flag;
+            })
+        },
+        Instruction::SetIndex(nnn) => Box::new(move |state| {
+            state.program_counter += 2;
+            state.index_register = nnn;
+        }),
+        Instruction::AddIndex(x) => {
+            let x = x as usize;
+            Box::new(move |state| {
+                state.program_counter += 2;
+                state.index_register = state.index_register.wrapping_add(state.registers[x] as u16);
+            })
+        },
+        _ => interpret(instruction),
+    }
+}
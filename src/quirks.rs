@@ -0,0 +1,61 @@
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum QuirkPreset {
+    #[default]
+    Chip8,
+    Superchip,
+    Xochip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum EdgeMode {
+    Clip,
+    Wrap,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    pub vf_reset: bool,
+    pub increment_index: bool,
+    pub shift_uses_vy: bool,
+    pub jump_uses_vx: bool,
+    pub horizontal_edge: EdgeMode,
+    pub vertical_edge: EdgeMode,
+    pub display_wait: bool,
+}
+impl From<QuirkPreset> for Quirks {
+    fn from(preset: QuirkPreset) -> Self {
+        match preset {
+            QuirkPreset::Chip8 => Self {
+                vf_reset: true,
+                increment_index: true,
+                shift_uses_vy: true,
+                jump_uses_vx: false,
+                horizontal_edge: EdgeMode::Clip,
+                vertical_edge: EdgeMode::Clip,
+                display_wait: true,
+            },
+            QuirkPreset::Superchip => Self {
+                vf_reset: false,
+                increment_index: false,
+                shift_uses_vy: false,
+                jump_uses_vx: true,
+                horizontal_edge: EdgeMode::Clip,
+                vertical_edge: EdgeMode::Clip,
+                display_wait: false,
+            },
+            QuirkPreset::Xochip => Self {
+                vf_reset: false,
+                increment_index: true,
+                shift_uses_vy: true,
+                jump_uses_vx: false,
+                horizontal_edge: EdgeMode::Wrap,
+                vertical_edge: EdgeMode::Wrap,
+                display_wait: false,
+            },
+        }
+    }
+}
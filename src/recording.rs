@@ -0,0 +1,221 @@
+use std::{fs::File, io::{self, Write, BufWriter}, path::Path, sync::mpsc::{self, Sender}, thread::{self, JoinHandle}};
+use serde::Deserialize;
+use crate::{framebuffer::Framebuffer, palette::{Palette, Color}};
+
+const FRAMES_PER_SECOND: u32 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingFormat {
+    #[default]
+    Gif,
+    Y4m,
+}
+impl RecordingFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            RecordingFormat::Gif => "gif",
+            RecordingFormat::Y4m => "y4m",
+        }
+    }
+}
+
+trait FrameWriter {
+    fn write_frame(&mut self, framebuffer: &Framebuffer) -> io::Result<()>;
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+struct GifWriter {
+    encoder: gif::Encoder<BufWriter<File>>,
+    scale: usize,
+    frame: u32,
+    pending: Option<(Framebuffer, u16)>,
+}
+impl GifWriter {
+    fn new(file: File, palette: &Palette, scale: usize, (width, height): (usize, usize)) -> io::Result<Self> {
+        let colors: Vec<u8> = palette.colors.iter().flat_map(|color| [color.r, color.g, color.b]).collect();
+        let mut encoder = gif::Encoder::new(
+            BufWriter::new(file),
+            (width * scale) as u16,
+            (height * scale) as u16,
+            &colors,
+        ).map_err(io::Error::other)?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(io::Error::other)?;
+
+        Ok(Self {
+            encoder,
+            scale,
+            frame: 0,
+            pending: None,
+        })
+    }
+    fn flush_pending(&mut self) -> io::Result<()> {
+        if let Some((framebuffer, delay)) = self.pending.take() {
+            let width = framebuffer.width() * self.scale;
+            let height = framebuffer.height() * self.scale;
+            let mut indices = Vec::with_capacity(width * height);
+            for y in 0..height {
+                for x in 0..width {
+                    indices.push(framebuffer.color_index(x / self.scale, y / self.scale));
+                }
+            }
+            let mut frame = gif::Frame::from_indexed_pixels(width as u16, height as u16, indices, None);
+            frame.delay = delay;
+            self.encoder.write_frame(&frame).map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+}
+impl FrameWriter for GifWriter {
+    fn write_frame(&mut self, framebuffer: &Framebuffer) -> io::Result<()> {
+        // gif delays are in hundredths of a second, so frames alternate between
+        // delays of 1 and 2 to average out at 60 frames per second
+        let delay = ((self.frame + 1) * 100 / FRAMES_PER_SECOND - self.frame * 100 / FRAMES_PER_SECOND) as u16;
+        self.frame = (self.frame + 1) % FRAMES_PER_SECOND;
+
+        match &mut self.pending {
+            // gif delays are 16-bit, so a long still is split across several frames
+            Some((pending, pending_delay)) if pending == framebuffer && pending_delay.checked_add(delay).is_some() => {
+                *pending_delay += delay;
+            },
+            _ => {
+                self.flush_pending()?;
+                self.pending = Some((framebuffer.clone(), delay));
+            },
+        }
+        Ok(())
+    }
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush_pending()?;
+        self.encoder.get_mut().flush()
+    }
+}
+
+struct Y4mWriter {
+    writer: BufWriter<File>,
+    scale: usize,
+    colors: [[u8; 3]; 4],
+    planes: Vec<u8>,
+}
+impl Y4mWriter {
+    fn new(file: File, palette: &Palette, scale: usize, (width, height): (usize, usize)) -> io::Result<Self> {
+        fn yuv(color: Color) -> [u8; 3] {
+            let (r, g, b) = (color.r as f32, color.g as f32, color.b as f32);
+            let y = 16.0 + 0.257 * r + 0.504 * g + 0.098 * b;
+            let u = 128.0 - 0.148 * r - 0.291 * g + 0.439 * b;
+            let v = 128.0 + 0.439 * r - 0.368 * g - 0.071 * b;
+            [y.round() as u8, u.round() as u8, v.round() as u8]
+        }
+
+        let mut writer = BufWriter::new(file);
+        writeln!(writer, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", width * scale, height * scale, FRAMES_PER_SECOND)?;
+
+        Ok(Self {
+            writer,
+            scale,
+            colors: palette.colors.map(yuv),
+            planes: Vec::new(),
+        })
+    }
+}
+impl FrameWriter for Y4mWriter {
+    fn write_frame(&mut self, framebuffer: &Framebuffer) -> io::Result<()> {
+        let width = framebuffer.width() * self.scale;
+        let height = framebuffer.height() * self.scale;
+        self.planes.resize(width * height * 3, 0);
+        for y in 0..height {
+            for x in 0..width {
+                let color = self.colors[framebuffer.color_index(x / self.scale, y / self.scale) as usize];
+                for (plane, component) in color.iter().enumerate() {
+                    self.planes[plane * width * height + y * width + x] = *component;
+                }
+            }
+        }
+        self.writer.write_all(b"FRAME\n")?;
+        self.writer.write_all(&self.planes)
+    }
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+pub struct Recorder {
+    frame_tx: Sender<Framebuffer>,
+    thread: JoinHandle<io::Result<()>>,
+}
+impl Recorder {
+    pub fn start(path: &Path, format: RecordingFormat, palette: &Palette, scale: u32) -> io::Result<Self> {
+        let file = File::create(path)?;
+        let palette = *palette;
+        let scale = scale.max(1) as usize;
+
+        let (frame_tx, frame_rx) = mpsc::channel::<Framebuffer>();
+        let thread = thread::spawn(move || {
+            let mut frames = frame_rx.iter().peekable();
+            let Some(first) = frames.peek() else {
+                return Ok(());
+            };
+            let size = (first.width(), first.height());
+            let mut writer: Box<dyn FrameWriter + Send> = match format {
+                RecordingFormat::Gif => Box::new(GifWriter::new(file, &palette, scale, size)?),
+                RecordingFormat::Y4m => Box::new(Y4mWriter::new(file, &palette, scale, size)?),
+            };
+            for framebuffer in frames {
+                if (framebuffer.width(), framebuffer.height()) != size {
+                    writer.finish()?;
+                    return Err(io::Error::other("display resolution changed during recording"));
+                }
+                writer.write_frame(&framebuffer)?;
+            }
+            writer.finish()
+        });
+
+        Ok(Self {
+            frame_tx,
+            thread,
+        })
+    }
+    pub fn record_frame(&self, framebuffer: &Framebuffer) {
+        let _ = self.frame_tx.send(framebuffer.clone());
+    }
+    pub fn finish(self) -> io::Result<()> {
+        drop(self.frame_tx);
+        self.thread.join().unwrap_or_else(|_| Err(io::Error::other("recording thread panicked")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+    use crate::quirks::EdgeMode;
+    use super::*;
+
+    #[test]
+    fn long_stills_split_into_frames_without_wrapping_delays() {
+        let path = env::temp_dir().join(format!("emu8-still-{}.gif", std::process::id()));
+        let mut writer = GifWriter::new(File::create(&path).unwrap(), &Palette::default(), 1, (64, 32)).unwrap();
+        // 700 seconds of the same frame is 70000 hundredths, more than one gif delay holds
+        let still = Framebuffer::new();
+        for _ in 0..700 * FRAMES_PER_SECOND {
+            writer.write_frame(&still).unwrap();
+        }
+        let mut moved = still.clone();
+        moved.draw(0, 0, &[0x80], EdgeMode::Clip, EdgeMode::Clip);
+        writer.write_frame(&moved).unwrap();
+        writer.finish().unwrap();
+        drop(writer);
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(File::open(&path).unwrap()).unwrap();
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+        }
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(delays.len(), 3);
+        assert_eq!(delays[..2].iter().map(|&delay| delay as u32).sum::<u32>(), 70_000);
+        assert_eq!(delays[2], 1);
+    }
+}
@@ -0,0 +1,123 @@
+use std::{fmt, fs, io, ops::Range, path::{Path, PathBuf}};
+use byteorder::{ReadBytesExt, BigEndian};
+use crate::processor::{self, MEMORY_SIZE, FONT_RANGE};
+
+#[derive(Debug, Clone)]
+pub struct Rom {
+    path: PathBuf,
+    data: Vec<u8>,
+}
+impl Rom {
+    pub fn new(path: PathBuf, data: Vec<u8>) -> Self {
+        Self { path, data }
+    }
+    pub fn load(path: &Path) -> Result<Self, LoadError> {
+        let data = fs::read(path)?;
+        if data.is_empty() {
+            return Err(LoadError::Empty);
+        }
+        Ok(Self {
+            path: path.to_owned(),
+            data,
+        })
+    }
+    pub fn reload(&self) -> Result<Self, LoadError> {
+        Self::load(&self.path)
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    pub fn name(&self) -> String {
+        self.path
+            .file_stem()
+            .unwrap_or(self.path.as_os_str())
+            .to_string_lossy()
+            .into_owned()
+    }
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+    pub fn check(&self, start_address: u16) -> Result<(), LoadError> {
+        let start = start_address as usize;
+        if self.data.is_empty() {
+            return Err(LoadError::Empty);
+        }
+        if !(FONT_RANGE.end..MEMORY_SIZE).contains(&start) {
+            return Err(LoadError::Overlap(start..start + self.data.len()));
+        }
+        if self.data.len() > MEMORY_SIZE - start {
+            return Err(LoadError::TooLarge {
+                size: self.data.len(),
+                max_size: MEMORY_SIZE - start,
+            });
+        }
+
+        if !self.data.len().is_multiple_of(2) {
+            log::warn!("{} has an odd length of {} bytes", self.name(), self.data.len());
+        }
+        match self.data.as_slice().read_u16::<BigEndian>() {
+            Ok(opcode) if processor::is_valid_opcode(opcode) => {},
+            Ok(opcode) => log::warn!("{} does not start with a valid instruction ({:04x})", self.name(), opcode),
+            Err(_) => log::warn!("{} is too short to contain an instruction", self.name()),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Empty,
+    TooLarge { size: usize, max_size: usize },
+    Overlap(Range<usize>),
+}
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(error) => write!(f, "{}", error),
+            LoadError::Empty => write!(f, "rom is empty"),
+            LoadError::TooLarge { size, max_size } => write!(f, "rom is {} bytes, but at most {} bytes fit in memory", size, max_size),
+            LoadError::Overlap(range) => write!(f, "rom at {:#05x}..{:#05x} overlaps the interpreter area or lies outside memory", range.start, range.end),
+        }
+    }
+}
+impl std::error::Error for LoadError {}
+impl From<io::Error> for LoadError {
+    fn from(error: io::Error) -> Self {
+        LoadError::Io(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(size: usize) -> Rom {
+        Rom::new(PathBuf::from("test.ch8"), vec![0x00; size])
+    }
+
+    #[test]
+    fn empty_roms_are_rejected() {
+        assert!(matches!(rom(0).check(0x200), Err(LoadError::Empty)));
+    }
+
+    #[test]
+    fn roms_may_fill_memory_exactly() {
+        assert!(rom(MEMORY_SIZE - 0x200).check(0x200).is_ok());
+        match rom(MEMORY_SIZE - 0x200 + 1).check(0x200) {
+            Err(LoadError::TooLarge { size, max_size }) => {
+                assert_eq!(size, MEMORY_SIZE - 0x200 + 1);
+                assert_eq!(max_size, MEMORY_SIZE - 0x200);
+            },
+            result => panic!("expected TooLarge, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn roms_must_start_between_the_font_and_the_end_of_memory() {
+        assert!(matches!(rom(2).check(0x9f), Err(LoadError::Overlap(range)) if range == (0x9f..0xa1)));
+        assert!(rom(2).check(0xa0).is_ok());
+        assert!(rom(2).check(0xffe).is_ok());
+        assert!(matches!(rom(2).check(0x1000), Err(LoadError::Overlap(range)) if range == (0x1000..0x1002)));
+    }
+}
@@ -0,0 +1,33 @@
+use std::{fs::File, io::{self, BufWriter}, path::{Path, PathBuf}};
+use crate::{framebuffer::Framebuffer, palette::Palette};
+
+pub fn render_rgb(framebuffer: &Framebuffer, palette: &Palette, scale: u32) -> Vec<u8> {
+    let scale = scale as usize;
+    let width = framebuffer.width() * scale;
+    let height = framebuffer.height() * scale;
+    let mut pixels = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            let color = palette.colors[framebuffer.color_index(x / scale, y / scale) as usize];
+            pixels.extend_from_slice(&[color.r, color.g, color.b]);
+        }
+    }
+    pixels
+}
+
+pub fn save_png(path: &Path, framebuffer: &Framebuffer, palette: &Palette, scale: u32) -> io::Result<()> {
+    let scale = scale.max(1);
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, framebuffer.width() as u32 * scale, framebuffer.height() as u32 * scale);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&render_rgb(framebuffer, palette, scale))?;
+    writer.finish()?;
+    Ok(())
+}
+
+pub fn timestamped_path(directory: &Path, name: &str, extension: &str) -> PathBuf {
+    let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S-%3f");
+    directory.join(format!("{}-{}.{}", name, timestamp, extension))
+}
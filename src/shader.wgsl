@@ -18,16 +18,25 @@ fn vs_main(
     return out;
 }
 
+struct Palette {
+    colors: array<vec4<f32>, 4>;
+};
+
 [[group(0), binding(0)]]
-var texture: texture_1d<u32>;
+var texture: texture_2d<u32>;
+[[group(0), binding(1)]]
+var<uniform> palette: Palette;
+[[group(0), binding(2)]]
+var persistence: texture_2d<f32>;
 
 [[stage(fragment)]]
 fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
-    let x = u32(in.tex_coords.x);
-    let y = u32(in.tex_coords.y);
-    if (extractBits(textureLoad(texture, i32(((y * 64u) + x) / 8u), 0).r, x % 8u, 1u) != 0u) {
-        return vec4<f32>(1.0, 1.0, 1.0, 1.0);
-    } else {
-        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    let size = textureDimensions(texture);
+    let pixel = min(vec2<i32>(in.tex_coords * vec2<f32>(size)), size - vec2<i32>(1));
+    let index = min(textureLoad(texture, pixel, 0).r, 3u);
+    if (index != 0u) {
+        return palette.colors[index];
     }
+    let intensity = textureLoad(persistence, pixel, 0).r;
+    return mix(palette.colors[0], palette.colors[1], intensity);
 }
\ No newline at end of file
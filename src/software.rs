@@ -0,0 +1,99 @@
+use std::rc::Rc;
+use softbuffer::GraphicsContext;
+use winit::{window::Window, dpi::PhysicalSize};
+use crate::{framebuffer::Framebuffer, palette::{Palette, Color}, config::Config, display::{self, DisplayError}};
+
+fn pack(color: Color) -> u32 {
+    (color.r as u32) << 16 | (color.g as u32) << 8 | color.b as u32
+}
+
+fn blend(background: Color, foreground: Color, intensity: u8) -> u32 {
+    let mix = |from: u8, to: u8| (from as i32 + (to as i32 - from as i32) * intensity as i32 / u8::MAX as i32) as u8;
+    pack(Color::rgb(
+        mix(background.r, foreground.r),
+        mix(background.g, foreground.g),
+        mix(background.b, foreground.b),
+    ))
+}
+
+pub struct SoftwareDisplay {
+    context: GraphicsContext<Rc<Window>>,
+    size: PhysicalSize<u32>,
+    palette: Palette,
+    intensities: Vec<u8>,
+    integer_scaling: bool,
+    framebuffer: Framebuffer,
+    buffer: Vec<u32>,
+}
+impl SoftwareDisplay {
+    pub fn new(window: Rc<Window>, config: &Config) -> Result<Self, DisplayError> {
+        if config.crt.enabled || !config.shaders.is_empty() {
+            log::warn!("post-processing shaders are not supported by the software renderer");
+        }
+        let size = window.inner_size();
+        let context = unsafe { GraphicsContext::new(window) }
+            .map_err(|error| DisplayError::Software(error.to_string()))?;
+
+        Ok(Self {
+            context,
+            size,
+            palette: config.palette,
+            intensities: Vec::new(),
+            integer_scaling: config.integer_scaling,
+            framebuffer: Framebuffer::new(),
+            buffer: Vec::new(),
+        })
+    }
+    pub fn size(&self) -> PhysicalSize<u32> {
+        self.size
+    }
+    pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        if new_size.width > 0 && new_size.height > 0 {
+            self.size = new_size;
+        }
+    }
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+    pub fn update(&mut self, framebuffer: &Framebuffer, intensities: &[u8]) {
+        self.framebuffer.clone_from(framebuffer);
+        self.intensities.clear();
+        self.intensities.extend_from_slice(intensities);
+    }
+    pub fn animating(&self) -> bool {
+        false
+    }
+    pub fn render(&mut self) {
+        let width = self.size.width.min(u16::MAX as u32) as usize;
+        let height = self.size.height.min(u16::MAX as u32) as usize;
+        let (source_width, source_height) = (self.framebuffer.width(), self.framebuffer.height());
+        let (left, top, viewport_width, viewport_height) = display::viewport(
+            self.size,
+            (source_width as u32, source_height as u32),
+            self.integer_scaling,
+        );
+        let (left, top) = (left.max(0.0) as usize, top.max(0.0) as usize);
+        let (viewport_width, viewport_height) = ((viewport_width as usize).max(1), (viewport_height as usize).max(1));
+        let right = (left + viewport_width).min(width);
+        let bottom = (top + viewport_height).min(height);
+
+        let colors = self.palette.colors.map(pack);
+        let intensities = &self.intensities;
+        self.buffer.clear();
+        self.buffer.resize(width * height, colors[0]);
+        for y in top..bottom {
+            let source_y = ((y - top) * source_height / viewport_height).min(source_height - 1);
+            for x in left..right {
+                let source_x = ((x - left) * source_width / viewport_width).min(source_width - 1);
+                self.buffer[y * width + x] = match self.framebuffer.color_index(source_x, source_y) {
+                    0 => match intensities.get(source_y * source_width + source_x).copied().unwrap_or(0) {
+                        0 => colors[0],
+                        intensity => blend(self.palette.background(), self.palette.foreground(), intensity),
+                    },
+                    index => colors[index as usize],
+                };
+            }
+        }
+        self.context.set_buffer(&self.buffer, width as u16, height as u16);
+    }
+}
@@ -0,0 +1,191 @@
+use std::{fmt::Write as _, io::{self, Write}, time::{Duration, Instant}};
+use crossterm::{cursor, event::{self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags}, style::{self, Color, Print}, terminal, execute, queue};
+use serde::Deserialize;
+use crate::{framebuffer::Framebuffer, input::{self, Key, KeyState}, palette, processor::{ProcessorState, FRAME_DURATION}, timing::Timing, config::Config, Rom, RunError};
+
+const KEY_HOLD: Duration = Duration::from_millis(150);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum TerminalRendering {
+    #[default]
+    HalfBlock,
+    Braille,
+}
+
+fn color(color: palette::Color) -> Color {
+    Color::Rgb {
+        r: color.r,
+        g: color.g,
+        b: color.b,
+    }
+}
+
+struct Terminal {
+    stdout: io::Stdout,
+    key_releases: bool,
+    previous: Option<(Framebuffer, String)>,
+}
+impl Terminal {
+    fn enter() -> io::Result<Self> {
+        let mut stdout = io::stdout();
+        terminal::enable_raw_mode()?;
+        execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide, terminal::Clear(terminal::ClearType::All))?;
+        let key_releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if key_releases {
+            execute!(stdout, event::PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+
+        Ok(Self {
+            stdout,
+            key_releases,
+            previous: None,
+        })
+    }
+    fn redraw(&mut self) -> io::Result<()> {
+        self.previous = None;
+        execute!(self.stdout, style::ResetColor, terminal::Clear(terminal::ClearType::All))
+    }
+    fn draw(&mut self, framebuffer: &Framebuffer, status: String, rendering: TerminalRendering, palette: &palette::Palette) -> io::Result<()> {
+        if self.previous.as_ref().is_some_and(|(previous, previous_status)| previous == framebuffer && *previous_status == status) {
+            return Ok(());
+        }
+        let (columns, _) = terminal::size()?;
+        let (width, height) = (framebuffer.width(), framebuffer.height());
+
+        let (cell_width, cell_height) = match rendering {
+            TerminalRendering::HalfBlock => (1, 2),
+            TerminalRendering::Braille => (2, 4),
+        };
+        for row in 0..height.div_ceil(cell_height) {
+            queue!(self.stdout, cursor::MoveTo(0, row as u16))?;
+            let mut colors = None;
+            for column in 0..width.div_ceil(cell_width).min(columns as usize) {
+                let (x, y) = (column * cell_width, row * cell_height);
+                let pixel = |dx: usize, dy: usize| match (x + dx < width, y + dy < height) {
+                    (true, true) => framebuffer.color_index(x + dx, y + dy),
+                    _ => 0,
+                };
+                let (glyph, foreground, background) = match rendering {
+                    TerminalRendering::HalfBlock => ('▀', palette.colors[pixel(0, 0) as usize], palette.colors[pixel(0, 1) as usize]),
+                    TerminalRendering::Braille => {
+                        const DOTS: [(usize, usize); 8] = [(0, 0), (0, 1), (0, 2), (1, 0), (1, 1), (1, 2), (0, 3), (1, 3)];
+                        let bits = DOTS.iter().enumerate()
+                            .filter(|(_, (dx, dy))| pixel(*dx, *dy) != 0)
+                            .fold(0, |bits, (bit, _)| bits | 1 << bit);
+                        (char::from_u32(0x2800 + bits).unwrap(), palette.foreground(), palette.background())
+                    },
+                };
+                if colors != Some((foreground, background)) {
+                    colors = Some((foreground, background));
+                    queue!(self.stdout, style::SetColors(style::Colors::new(color(foreground), color(background))))?;
+                }
+                queue!(self.stdout, Print(glyph))?;
+            }
+        }
+
+        let status_row = height.div_ceil(cell_height) as u16;
+        let status_line: String = status.chars().take(columns as usize).collect();
+        queue!(
+            self.stdout,
+            style::ResetColor,
+            cursor::MoveTo(0, status_row),
+            terminal::Clear(terminal::ClearType::CurrentLine),
+            Print(&status_line),
+        )?;
+        self.stdout.flush()?;
+        self.previous = Some((framebuffer.clone(), status));
+        Ok(())
+    }
+}
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if self.key_releases {
+            let _ = execute!(self.stdout, event::PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(self.stdout, style::ResetColor, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn status(state: &ProcessorState) -> String {
+    let mut status = format!("PC {:#05x}  I {:#05x} ", state.program_counter(), state.index_register());
+    for (index, register) in state.registers().iter().enumerate() {
+        let _ = write!(status, " V{:X} {:02x}", index, register);
+    }
+    let _ = match state.timing() {
+        Timing::Instructions => write!(status, "  speed {}", state.speed()),
+        Timing::Vip => write!(status, "  vip timing"),
+    };
+    status
+}
+
+pub fn run_terminal(rom: &Rom, config: &Config) -> Result<(), RunError> {
+    rom.check(config.start_address)?;
+    let (input_tx, input_rx) = input::input(config.hold_taps);
+    let mut state = ProcessorState::new(input_rx, rom.data(), config)?;
+
+    let mut terminal = Terminal::enter()?;
+    // terminals without the keyboard enhancement protocol only report presses,
+    // so keys are released once they have not been repeated for a while
+    let mut key_deadlines: [Option<Instant>; 16] = [None; 16];
+    let mut sound_playing = false;
+    let mut next_frame = Instant::now();
+    loop {
+        while event::poll(next_frame.saturating_duration_since(Instant::now()))? {
+            match event::read()? {
+                Event::Key(key_event) if key_event.code == KeyCode::Esc
+                    || (key_event.code == KeyCode::Char('c') && key_event.modifiers.contains(KeyModifiers::CONTROL)) => {
+                    return Ok(());
+                },
+                Event::Key(key_event) => {
+                    let Some(key) = (match key_event.code {
+                        KeyCode::Char(character) => Key::from_char(character),
+                        _ => None,
+                    }) else {
+                        continue;
+                    };
+                    let deadline = &mut key_deadlines[key as usize];
+                    match key_event.kind {
+                        KeyEventKind::Press if terminal.key_releases => {
+                            let _ = input_tx.send_key(key, KeyState::Pressed);
+                        },
+                        KeyEventKind::Release => {
+                            let _ = input_tx.send_key(key, KeyState::Released);
+                        },
+                        KeyEventKind::Press | KeyEventKind::Repeat if !terminal.key_releases => {
+                            if deadline.is_none() {
+                                let _ = input_tx.send_key(key, KeyState::Pressed);
+                            }
+                            *deadline = Some(Instant::now() + KEY_HOLD);
+                        },
+                        _ => {},
+                    }
+                },
+                Event::Resize(..) => terminal.redraw()?,
+                _ => {},
+            }
+        }
+
+        let now = Instant::now();
+        for (index, deadline) in key_deadlines.iter_mut().enumerate() {
+            if deadline.is_some_and(|deadline| deadline <= now) {
+                *deadline = None;
+                let _ = input_tx.send_key(Key::ALL[index], KeyState::Released);
+            }
+        }
+        state.run_frame(now)?;
+
+        if state.sound_playing() && !sound_playing && !config.mute {
+            execute!(terminal.stdout, Print('\x07'))?;
+        }
+        sound_playing = state.sound_playing();
+        let status = status(&state);
+        terminal.draw(state.framebuffer(), status, config.terminal_rendering, &config.palette)?;
+
+        next_frame += FRAME_DURATION;
+        if next_frame < now {
+            next_frame = now;
+        }
+    }
+}
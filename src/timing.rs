@@ -0,0 +1,52 @@
+use serde::Deserialize;
+use crate::instruction::Instruction;
+
+// the vip's 1802 runs at 1.7609 MHz with 8 clocks per machine cycle, and the
+// display interrupt and its dma steal part of every 60 Hz frame
+pub(crate) const VIP_CYCLES_PER_FRAME: i64 = 1_760_900 / 8 / 60;
+pub(crate) const VIP_DISPLAY_CYCLES: i64 = 1024 + 46;
+const VIP_FETCH_CYCLES: u32 = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Timing {
+    #[default]
+    Instructions,
+    Vip,
+}
+
+pub(crate) fn vip_cycles(instruction: Instruction, registers: &[u8; 16]) -> u32 {
+    VIP_FETCH_CYCLES + match instruction {
+        Instruction::Clear => 3078,
+        Instruction::Return => 10,
+        Instruction::MachineCall(_) | Instruction::Invalid => 0,
+        Instruction::Jump(_) => 12,
+        Instruction::Call(_) => 26,
+        Instruction::SkipEqual(..) | Instruction::SkipNotEqual(..) => 10,
+        Instruction::SkipRegistersEqual(..) | Instruction::SkipRegistersNotEqual(..) => 14,
+        Instruction::Set(..) => 6,
+        Instruction::Add(..) => 10,
+        Instruction::Move(..) => 12,
+        Instruction::Or(..) | Instruction::And(..) | Instruction::Xor(..) | Instruction::AddRegisters(..)
+        | Instruction::Subtract(..) | Instruction::ShiftRight(..) | Instruction::SubtractReverse(..) | Instruction::ShiftLeft(..) => 44,
+        Instruction::SetIndex(_) => 12,
+        Instruction::JumpOffset(..) => 22,
+        Instruction::Random(..) => 36,
+        Instruction::Draw(x, _, n) => {
+            let row_cycles = match registers[x as usize] % 8 {
+                0 => 46,
+                _ => 70,
+            };
+            68 + n as u32 * row_cycles
+        },
+        Instruction::SkipKeyPressed(_) | Instruction::SkipKeyNotPressed(_) => 14,
+        Instruction::AddIndex(_) | Instruction::FontCharacter(_) => 16,
+        // bcd conversion counts each digit up by repeated subtraction
+        Instruction::StoreBcd(x) => {
+            let value = registers[x as usize] as u32;
+            84 + 16 * (value / 100 + value / 10 % 10 + value % 10)
+        },
+        Instruction::StoreRegisters(x) | Instruction::LoadRegisters(x) => 14 + 14 * (x as u32 + 1),
+        Instruction::GetDelay(_) | Instruction::WaitKey(_) | Instruction::SetDelay(_) | Instruction::SetSound(_) => 10,
+    }
+}